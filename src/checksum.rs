@@ -0,0 +1,219 @@
+use crate::tarballer::partial_path;
+use clap::ValueEnum;
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::path::Path;
+
+/// Hash algorithms that can be used for the checksum manifest
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Algorithm {
+    /// SHA-256, written to SHA256SUMS (check with `sha256sum -c`)
+    Sha256,
+    /// BLAKE3, written to B3SUMS (check with `b3sum -c`)
+    Blake3,
+}
+
+impl Algorithm {
+    /// File name of the manifest listing checksums made with this algorithm
+    pub fn manifest_name(&self) -> &'static str {
+        match self {
+            Algorithm::Sha256 => "SHA256SUMS",
+            Algorithm::Blake3 => "B3SUMS",
+        }
+    }
+
+    /// Starts a new hash using this algorithm
+    pub fn hasher(&self) -> Hasher {
+        match self {
+            Algorithm::Sha256 => Hasher::Sha256(Sha256::new()),
+            Algorithm::Blake3 => Hasher::Blake3(Box::new(blake3::Hasher::new())),
+        }
+    }
+}
+
+/// A hash in progress
+pub enum Hasher {
+    Sha256(Sha256),
+    Blake3(Box<blake3::Hasher>),
+}
+
+impl Hasher {
+    pub fn update(&mut self, data: &[u8]) {
+        match self {
+            Hasher::Sha256(hasher) => hasher.update(data),
+            Hasher::Blake3(hasher) => {
+                hasher.update(data);
+            }
+        }
+    }
+
+    /// Finishes the hash and returns it as lowercase hex
+    pub fn finalize_hex(self) -> String {
+        match self {
+            Hasher::Sha256(hasher) => hasher
+                .finalize()
+                .iter()
+                .map(|byte| format!("{:02x}", byte))
+                .collect(),
+            Hasher::Blake3(hasher) => hasher.finalize().to_hex().to_string(),
+        }
+    }
+}
+
+/// Writer that hashes every byte on its way to the inner writer
+pub struct HashingWriter<W> {
+    inner: W,
+    hasher: Option<Hasher>,
+}
+
+impl<W: Write> HashingWriter<W> {
+    /// Wraps `inner`, hashing everything written if a hasher is given
+    pub fn new(inner: W, hasher: Option<Hasher>) -> Self {
+        HashingWriter { inner, hasher }
+    }
+
+    /// Returns the inner writer and the hex digest of everything written to it
+    pub fn finish(self) -> (W, Option<String>) {
+        (self.inner, self.hasher.map(Hasher::finalize_hex))
+    }
+}
+
+impl<W: Write> Write for HashingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        if let Some(hasher) = &mut self.hasher {
+            hasher.update(&buf[..written]);
+        }
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Adds checksums to the manifest in `dir`, replacing any existing lines for the same files
+///
+/// The manifest uses the `<hex>  <file name>` format understood by `sha256sum -c` and `b3sum -c`.
+pub fn write_manifest(
+    dir: &Path,
+    algorithm: Algorithm,
+    checksums: &[(String, String)],
+) -> std::io::Result<()> {
+    let manifest_path = dir.join(algorithm.manifest_name());
+    let mut lines = BTreeMap::new();
+    match std::fs::read_to_string(&manifest_path) {
+        Ok(existing) => {
+            for line in existing.lines() {
+                if let Some((digest, name)) = line.split_once("  ") {
+                    lines.insert(name.to_string(), digest.to_string());
+                }
+            }
+        }
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+        Err(e) => return Err(e),
+    }
+    for (name, digest) in checksums {
+        lines.insert(name.clone(), digest.clone());
+    }
+    // like tarballs, the manifest is only renamed into place once it is complete
+    let partial_path = partial_path(&manifest_path);
+    let written = write_lines(&partial_path, &lines)
+        .and_then(|_| std::fs::rename(&partial_path, &manifest_path));
+    if written.is_err() {
+        let _ = std::fs::remove_file(&partial_path);
+    }
+    written
+}
+
+/// Writes and syncs manifest lines to `path`
+fn write_lines(path: &Path, lines: &BTreeMap<String, String>) -> std::io::Result<()> {
+    let mut manifest = std::fs::File::create(path)?;
+    for (name, digest) in lines {
+        writeln!(manifest, "{}  {}", digest, name)?;
+    }
+    manifest.sync_all()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hex(algorithm: Algorithm, data: &[u8]) -> String {
+        let mut writer = HashingWriter::new(Vec::new(), Some(algorithm.hasher()));
+        writer.write_all(data).unwrap();
+        let (written, digest) = writer.finish();
+        assert_eq!(written, data);
+        digest.unwrap()
+    }
+
+    #[test]
+    fn hashes_everything_written() {
+        assert_eq!(
+            hex(Algorithm::Sha256, b"abc"),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        assert_eq!(
+            hex(Algorithm::Blake3, b"abc"),
+            "6437b3ac38465133ffb63b75273a8db548c558465d79db03fd359c6cd5bd9d85"
+        );
+    }
+
+    #[test]
+    fn manifest_lines_are_merged_and_sorted() {
+        let dir = tempfile::tempdir().unwrap();
+        let checksums = |pairs: &[(&str, &str)]| {
+            pairs
+                .iter()
+                .map(|(name, digest)| (name.to_string(), digest.to_string()))
+                .collect::<Vec<_>>()
+        };
+        write_manifest(
+            dir.path(),
+            Algorithm::Sha256,
+            &checksums(&[("b.tar", "22"), ("a.tar", "11")]),
+        )
+        .unwrap();
+        // a later run replaces the lines for archives it wrote again and keeps the rest
+        write_manifest(
+            dir.path(),
+            Algorithm::Sha256,
+            &checksums(&[("b.tar", "33"), ("sub/c.tar", "44")]),
+        )
+        .unwrap();
+        assert_eq!(
+            std::fs::read_to_string(dir.path().join("SHA256SUMS")).unwrap(),
+            "11  a.tar\n33  b.tar\n44  sub/c.tar\n"
+        );
+        assert!(!partial_path(&dir.path().join("SHA256SUMS")).exists());
+    }
+
+    #[test]
+    fn manifest_can_be_checked_with_sha256sum() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir(dir.path().join("sub")).unwrap();
+        let mut checksums = Vec::new();
+        for (name, contents) in [("a.tar", "hello"), ("sub/b.tar", "world")] {
+            std::fs::write(dir.path().join(name), contents).unwrap();
+            checksums.push((
+                name.to_string(),
+                hex(Algorithm::Sha256, contents.as_bytes()),
+            ));
+        }
+        write_manifest(dir.path(), Algorithm::Sha256, &checksums).unwrap();
+        // only where coreutils is installed
+        let Ok(output) = std::process::Command::new("sha256sum")
+            .args(["-c", "SHA256SUMS"])
+            .current_dir(dir.path())
+            .output()
+        else {
+            return;
+        };
+        assert!(output.status.success(), "{:?}", output);
+        assert_eq!(
+            String::from_utf8_lossy(&output.stdout),
+            "a.tar: OK\nsub/b.tar: OK\n"
+        );
+    }
+}
@@ -0,0 +1,374 @@
+use bzip2::read::MultiBzDecoder;
+use bzip2::write::BzEncoder;
+use clap::ValueEnum;
+use flate2::read::MultiGzDecoder;
+use flate2::write::GzEncoder;
+use std::fs::File;
+use std::io::{BufReader, Read, Write};
+use std::ops::RangeInclusive;
+use xz2::read::XzDecoder;
+use xz2::write::XzEncoder;
+
+/// Compression codecs that can be applied to a tarball
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum Compression {
+    /// Plain uncompressed tarball (.tar)
+    #[default]
+    None,
+    /// Gzip compressed tarball (.tar.gz)
+    Gzip,
+    /// Zstandard compressed tarball (.tar.zst)
+    Zstd,
+    /// xz (LZMA2) compressed tarball (.tar.xz)
+    Xz,
+    /// bzip2 compressed tarball (.tar.bz2)
+    Bzip2,
+}
+
+impl Compression {
+    /// Finds the codec of a tarball from its file name, returning the codec and the name without the extension
+    pub fn from_file_name(file_name: &str) -> Option<(Compression, &str)> {
+        Compression::value_variants().iter().find_map(|codec| {
+            file_name
+                .strip_suffix(codec.extension())
+                .and_then(|stem| stem.strip_suffix('.'))
+                .filter(|stem| !stem.is_empty())
+                .map(|stem| (*codec, stem))
+        })
+    }
+
+    /// Wraps a tarball file in the decoder for this codec
+    pub fn decoder(&self, file: File) -> std::io::Result<Box<dyn Read>> {
+        let file = BufReader::new(file);
+        match self {
+            Compression::None => Ok(Box::new(file)),
+            Compression::Gzip => Ok(Box::new(MultiGzDecoder::new(file))),
+            Compression::Zstd => {
+                let mut decoder = zstd::Decoder::with_buffer(file)?;
+                // accept archives written with any --long window
+                decoder.window_log_max(*WINDOW_LOG_RANGE.end())?;
+                Ok(Box::new(decoder))
+            }
+            Compression::Xz => Ok(Box::new(XzDecoder::new(file))),
+            Compression::Bzip2 => Ok(Box::new(MultiBzDecoder::new(file))),
+        }
+    }
+
+    /// File extension (without the leading dot) for a tarball using this codec
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Compression::None => "tar",
+            Compression::Gzip => "tar.gz",
+            Compression::Zstd => "tar.zst",
+            Compression::Xz => "tar.xz",
+            Compression::Bzip2 => "tar.bz2",
+        }
+    }
+
+    /// Range of compression levels accepted by this codec
+    pub fn level_range(&self) -> Option<RangeInclusive<u32>> {
+        match self {
+            Compression::None => None,
+            Compression::Gzip => Some(1..=9),
+            Compression::Zstd => Some(1..=22),
+            Compression::Xz => Some(0..=9),
+            Compression::Bzip2 => Some(1..=9),
+        }
+    }
+
+    /// Level used when none is given on the command line
+    pub fn default_level(&self) -> u32 {
+        match self {
+            Compression::None => 0,
+            Compression::Gzip => 6,
+            Compression::Zstd => 3,
+            Compression::Xz => 6,
+            Compression::Bzip2 => 9,
+        }
+    }
+}
+
+impl std::fmt::Display for Compression {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let value = self.to_possible_value().expect("no skipped variants");
+        f.write_str(value.get_name())
+    }
+}
+
+/// Range of window sizes (as a power of two) accepted by `--long`
+pub const WINDOW_LOG_RANGE: RangeInclusive<u32> = 10..=31;
+
+/// Codec and tuning options used to compress every tarball in a run
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Settings {
+    pub codec: Compression,
+    pub level: Option<u32>,
+    /// Window log for zstd long-distance matching
+    pub long: Option<u32>,
+    /// Number of zstd worker threads
+    pub threads: Option<u32>,
+}
+
+impl Settings {
+    /// Checks that every option given is valid for the chosen codec
+    pub fn validate(&self) -> Result<(), String> {
+        match (self.level, self.codec.level_range()) {
+            (Some(_), None) => {
+                return Err(format!(
+                    "--level has no effect without a compression codec (got --compress {})",
+                    self.codec
+                ))
+            }
+            (Some(level), Some(range)) if !range.contains(&level) => {
+                return Err(format!(
+                    "level {} is out of range for {} ({}-{})",
+                    level,
+                    self.codec,
+                    range.start(),
+                    range.end()
+                ))
+            }
+            _ => {}
+        }
+        if self.codec != Compression::Zstd {
+            if self.long.is_some() {
+                return Err(format!(
+                    "--long is only supported by zstd (got --compress {})",
+                    self.codec
+                ));
+            }
+            if self.threads.is_some() {
+                return Err(format!(
+                    "--threads is only supported by zstd (got --compress {})",
+                    self.codec
+                ));
+            }
+        }
+        if let Some(window_log) = self.long {
+            if !WINDOW_LOG_RANGE.contains(&window_log) {
+                return Err(format!(
+                    "--long window log {} is out of range ({}-{})",
+                    window_log,
+                    WINDOW_LOG_RANGE.start(),
+                    WINDOW_LOG_RANGE.end()
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Wraps the tarball writer in the encoder for the chosen codec
+    pub fn encoder<W: Write>(&self, file: W) -> std::io::Result<Encoder<W>> {
+        let level = self.level.unwrap_or_else(|| self.codec.default_level());
+        match self.codec {
+            Compression::None => Ok(Encoder::None(file)),
+            Compression::Gzip => Ok(Encoder::Gzip(GzEncoder::new(
+                file,
+                flate2::Compression::new(level),
+            ))),
+            Compression::Zstd => {
+                let mut encoder = zstd::Encoder::new(file, level as i32)?;
+                if let Some(window_log) = self.long {
+                    encoder.long_distance_matching(true)?;
+                    encoder.window_log(window_log)?;
+                }
+                if let Some(threads) = self.threads {
+                    encoder.multithread(threads)?;
+                }
+                Ok(Encoder::Zstd(encoder))
+            }
+            Compression::Xz => Ok(Encoder::Xz(XzEncoder::new(file, level))),
+            Compression::Bzip2 => Ok(Encoder::Bzip2(BzEncoder::new(
+                file,
+                bzip2::Compression::new(level),
+            ))),
+        }
+    }
+}
+
+/// Writer that compresses everything written to it before it reaches the tarball file
+pub enum Encoder<W: Write> {
+    None(W),
+    Gzip(GzEncoder<W>),
+    Zstd(zstd::Encoder<'static, W>),
+    Xz(XzEncoder<W>),
+    Bzip2(BzEncoder<W>),
+}
+
+impl<W: Write> Encoder<W> {
+    /// Flushes any buffered compressed data and writes the codec trailer
+    pub fn finish(self) -> std::io::Result<W> {
+        match self {
+            Encoder::None(file) => Ok(file),
+            Encoder::Gzip(encoder) => encoder.finish(),
+            Encoder::Zstd(encoder) => encoder.finish(),
+            Encoder::Xz(encoder) => encoder.finish(),
+            Encoder::Bzip2(encoder) => encoder.finish(),
+        }
+    }
+}
+
+impl<W: Write> Write for Encoder<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match self {
+            Encoder::None(file) => file.write(buf),
+            Encoder::Gzip(encoder) => encoder.write(buf),
+            Encoder::Zstd(encoder) => encoder.write(buf),
+            Encoder::Xz(encoder) => encoder.write(buf),
+            Encoder::Bzip2(encoder) => encoder.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self {
+            Encoder::None(file) => file.flush(),
+            Encoder::Gzip(encoder) => encoder.flush(),
+            Encoder::Zstd(encoder) => encoder.flush(),
+            Encoder::Xz(encoder) => encoder.flush(),
+            Encoder::Bzip2(encoder) => encoder.flush(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Compresses `data` into a file with `settings` and returns the file's bytes and what it decompresses to
+    fn round_trip(settings: Settings, data: &[u8]) -> (Vec<u8>, Vec<u8>) {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir
+            .path()
+            .join(format!("data.{}", settings.codec.extension()));
+        let mut encoder = settings.encoder(File::create(&path).unwrap()).unwrap();
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap();
+        let mut decompressed = Vec::new();
+        settings
+            .codec
+            .decoder(File::open(&path).unwrap())
+            .unwrap()
+            .read_to_end(&mut decompressed)
+            .unwrap();
+        (std::fs::read(&path).unwrap(), decompressed)
+    }
+
+    fn settings(codec: Compression, level: Option<u32>) -> Settings {
+        Settings {
+            codec,
+            level,
+            ..Settings::default()
+        }
+    }
+
+    #[test]
+    fn gzip_round_trips_at_every_level() {
+        let data = b"hello hello hello hello".repeat(100);
+        for level in [None, Some(1), Some(9)] {
+            let (file, decompressed) = round_trip(settings(Compression::Gzip, level), &data);
+            assert_eq!(&file[..2], [0x1f, 0x8b]);
+            assert!(file.len() < data.len());
+            assert_eq!(decompressed, data);
+        }
+    }
+
+    #[test]
+    fn rejects_levels_out_of_range() {
+        assert!(settings(Compression::Gzip, Some(9)).validate().is_ok());
+        assert!(settings(Compression::Gzip, Some(0)).validate().is_err());
+        assert!(settings(Compression::Gzip, Some(10)).validate().is_err());
+        // no codec, no level
+        assert!(settings(Compression::None, Some(1)).validate().is_err());
+    }
+
+    #[test]
+    fn finds_the_codec_from_the_file_name() {
+        assert_eq!(
+            Compression::from_file_name("foo.tar.gz"),
+            Some((Compression::Gzip, "foo"))
+        );
+        assert_eq!(
+            Compression::from_file_name("foo.bar.tar"),
+            Some((Compression::None, "foo.bar"))
+        );
+        assert_eq!(Compression::from_file_name(".tar.gz"), None);
+        assert_eq!(Compression::from_file_name("foo.gz"), None);
+    }
+
+    #[test]
+    fn zstd_round_trips_with_long_distance_matching_and_threads() {
+        let data = b"0123456789abcdef".repeat(10_000);
+        for (long, threads) in [
+            (None, None),
+            (Some(27), None),
+            (None, Some(2)),
+            (Some(31), Some(2)),
+        ] {
+            let settings = Settings {
+                codec: Compression::Zstd,
+                level: Some(19),
+                long,
+                threads,
+            };
+            settings.validate().unwrap();
+            let (file, decompressed) = round_trip(settings, &data);
+            assert_eq!(&file[..4], [0x28, 0xb5, 0x2f, 0xfd]);
+            assert_eq!(decompressed, data);
+        }
+    }
+
+    #[test]
+    fn long_and_threads_are_only_for_zstd() {
+        let zstd = Settings {
+            codec: Compression::Zstd,
+            long: Some(9),
+            ..Settings::default()
+        };
+        assert!(zstd.validate().is_err());
+        assert!(settings(Compression::Zstd, Some(22)).validate().is_ok());
+        for (long, threads) in [(Some(27), None), (None, Some(2))] {
+            let gzip = Settings {
+                codec: Compression::Gzip,
+                long,
+                threads,
+                ..Settings::default()
+            };
+            assert!(gzip.validate().is_err());
+        }
+    }
+
+    #[test]
+    fn xz_and_bzip2_round_trip() {
+        let data = b"hello hello hello hello".repeat(100);
+        for (codec, magic) in [
+            (Compression::Xz, &[0xfd, b'7', b'z', b'X', b'Z'][..]),
+            (Compression::Bzip2, &b"BZh"[..]),
+        ] {
+            let range = codec.level_range().unwrap();
+            for level in [None, Some(*range.start()), Some(*range.end())] {
+                let (file, decompressed) = round_trip(settings(codec, level), &data);
+                assert!(file.starts_with(magic), "{}", codec);
+                assert_eq!(decompressed, data);
+            }
+        }
+    }
+
+    #[test]
+    fn decodes_concatenated_streams() {
+        // bzip2 and gzip archives made by parallel compressors (pbzip2, pigz) are several streams back to back
+        for codec in [Compression::Bzip2, Compression::Gzip] {
+            let (first, _) = round_trip(settings(codec, None), b"hello ");
+            let (second, _) = round_trip(settings(codec, None), b"world");
+            let dir = tempfile::tempdir().unwrap();
+            let path = dir.path().join("data");
+            std::fs::write(&path, [first, second].concat()).unwrap();
+            let mut decompressed = String::new();
+            codec
+                .decoder(File::open(&path).unwrap())
+                .unwrap()
+                .read_to_string(&mut decompressed)
+                .unwrap();
+            assert_eq!(decompressed, "hello world");
+        }
+    }
+}
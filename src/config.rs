@@ -0,0 +1,363 @@
+use crate::error::Error;
+use clap::parser::ValueSource;
+use clap::{Arg, ArgAction, ArgMatches, Command};
+use std::collections::BTreeMap;
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+
+/// Name of the config file looked for in the target directory and the user config directory
+pub const FILE_NAME: &str = "wrap.toml";
+
+/// Options that choose the config file itself and so cannot be set from it
+const RESERVED: [&str; 4] = ["config", "profile", "help", "version"];
+
+/// Default options, and named profiles of options, loaded from a wrap.toml file
+///
+/// Keys are the long names of command line options (`dry-run = true`, `compress = "zstd"`,
+/// `exclude = ["target", "*.tmp"]`, `target-dir = "/srv/data"`), with dashes or underscores. Profiles are
+/// `[profile.<name>]` sections whose keys override the top level ones, along with any top level ones they conflict
+/// with.
+#[derive(Debug)]
+pub struct Config {
+    path: PathBuf,
+    table: toml::Table,
+}
+
+impl Config {
+    /// Finds the config file to use: `explicit` if given, otherwise wrap.toml in the target directory, otherwise
+    /// wrap.toml in the user config directory
+    pub fn find(explicit: Option<&Path>, target_dir: &Path) -> Option<PathBuf> {
+        if let Some(path) = explicit {
+            return Some(path.to_path_buf());
+        }
+        [Some(target_dir.to_path_buf()), user_config_dir()]
+            .into_iter()
+            .flatten()
+            .map(|dir| dir.join(FILE_NAME))
+            .find(|path| path.is_file())
+    }
+
+    /// Reads and parses a config file
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        let config_error = |message: String| Error::Config {
+            path: path.to_path_buf(),
+            message,
+        };
+        let text = std::fs::read_to_string(path).map_err(|e| config_error(e.to_string()))?;
+        let table = text
+            .parse::<toml::Table>()
+            .map_err(|e| config_error(e.to_string()))?;
+        Ok(Config {
+            path: path.to_path_buf(),
+            table,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Command line arguments for every value in the file (and the profile, if given) that `matches` did not
+    /// already get from the command line or the environment
+    ///
+    /// Values for options that conflict with one given on the command line or the environment are left out, so
+    /// e.g. tarball defaults do not get in the way of `--unwrap`. In the same way, top level values that conflict
+    /// with one from the profile are left out.
+    pub fn args(
+        &self,
+        profile: Option<&str>,
+        command: &Command,
+        matches: &ArgMatches,
+    ) -> Result<Vec<OsString>, Error> {
+        let config_error = |message: String| Error::Config {
+            path: self.path.clone(),
+            message,
+        };
+
+        // the profile's values, then the top level values it does not override
+        let mut profile_values = BTreeMap::new();
+        if let Some(name) = profile {
+            let profile = self
+                .table
+                .get("profile")
+                .and_then(|profiles| profiles.get(name))
+                .and_then(|profile| profile.as_table())
+                .ok_or_else(|| config_error(format!("no [profile.{}] section", name)))?;
+            for (key, value) in profile {
+                profile_values.insert(key.replace('_', "-"), value);
+            }
+        }
+        let mut top_values = BTreeMap::new();
+        for (key, value) in &self.table {
+            let key = key.replace('_', "-");
+            if key != "profile" && !profile_values.contains_key(&key) {
+                top_values.insert(key, value);
+            }
+        }
+
+        let is_given = |arg: &Arg| {
+            matches!(
+                matches.value_source(arg.get_id().as_str()),
+                Some(ValueSource::CommandLine | ValueSource::EnvVariable)
+            )
+        };
+        let is_active = |arg: &Arg| match arg.get_action() {
+            ArgAction::SetTrue => matches.get_flag(arg.get_id().as_str()),
+            _ => is_given(arg),
+        };
+        let conflicts = |a: &Arg, b: &Arg| {
+            command
+                .get_arg_conflicts_with(a)
+                .iter()
+                .chain(command.get_arg_conflicts_with(b).iter())
+                .any(|other| other.get_id() == a.get_id() || other.get_id() == b.get_id())
+        };
+
+        let mut args = Vec::new();
+        // options set by an earlier layer - Conflicts within a layer are left for clap to report
+        let mut accepted: Vec<&Arg> = Vec::new();
+        for values in [profile_values, top_values] {
+            let mut layer = Vec::new();
+            for (key, value) in values {
+                let arg = command
+                    .get_arguments()
+                    .find(|arg| {
+                        arg.get_long() == Some(key.as_str())
+                            || arg.get_id().as_str().replace('_', "-") == key
+                    })
+                    .filter(|arg| !RESERVED.contains(&arg.get_id().as_str()))
+                    .ok_or_else(|| config_error(format!("unknown option {:?}", key)))?;
+                if is_given(arg)
+                    || command
+                        .get_arguments()
+                        .any(|other| is_active(other) && conflicts(arg, other))
+                    || accepted.iter().any(|other| conflicts(arg, other))
+                {
+                    continue;
+                }
+                let values = arg_values(arg, value)
+                    .map_err(|message| config_error(format!("{:?} {}", key, message)))?;
+                if !values.is_empty() {
+                    layer.push(arg);
+                }
+                args.extend(values);
+            }
+            accepted.extend(layer);
+        }
+        Ok(args)
+    }
+}
+
+/// Command line arguments that set `arg` to `value`
+fn arg_values(arg: &Arg, value: &toml::Value) -> Result<Vec<OsString>, String> {
+    let scalar = |value: &toml::Value| match value {
+        toml::Value::String(value) => Some(value.clone()),
+        toml::Value::Integer(value) => Some(value.to_string()),
+        toml::Value::Float(value) => Some(value.to_string()),
+        _ => None,
+    };
+    let Some(long) = arg.get_long() else {
+        // the target directory
+        return scalar(value)
+            .map(|value| vec![value.into()])
+            .ok_or_else(|| "must be a string".to_string());
+    };
+    let optional_value = arg
+        .get_num_args()
+        .is_some_and(|num_args| num_args.min_values() == 0);
+    match (arg.get_action(), value) {
+        (ArgAction::SetTrue, toml::Value::Boolean(true)) => Ok(vec![format!("--{}", long).into()]),
+        (ArgAction::SetTrue, toml::Value::Boolean(false)) => Ok(Vec::new()),
+        (ArgAction::SetTrue, _) => Err("must be true or false".to_string()),
+        (ArgAction::Append, toml::Value::Array(items)) => items
+            .iter()
+            .map(|item| {
+                scalar(item)
+                    .map(|item| format!("--{}={}", long, item).into())
+                    .ok_or_else(|| "must be a list of strings or numbers".to_string())
+            })
+            .collect(),
+        (_, toml::Value::Boolean(true)) if optional_value => Ok(vec![format!("--{}", long).into()]),
+        (_, toml::Value::Boolean(false)) if optional_value => Ok(Vec::new()),
+        (_, value) => scalar(value)
+            .map(|value| vec![format!("--{}={}", long, value).into()])
+            .ok_or_else(|| "must be a string or a number".to_string()),
+    }
+}
+
+/// Directory wrap.toml is looked for in when the target directory does not have one
+fn user_config_dir() -> Option<PathBuf> {
+    let non_empty = |name: &str| std::env::var_os(name).filter(|value| !value.is_empty());
+    non_empty("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| non_empty("APPDATA").map(PathBuf::from))
+        .or_else(|| non_empty("HOME").map(|home| Path::new(&home).join(".config")))
+        .map(|dir| dir.join("wrap"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A cut down version of the `wrap` command line
+    fn command() -> Command {
+        Command::new("wrap")
+            .arg(
+                Arg::new("verbose")
+                    .long("verbose")
+                    .action(ArgAction::SetTrue),
+            )
+            .arg(
+                Arg::new("dry_run")
+                    .long("dry-run")
+                    .action(ArgAction::SetTrue),
+            )
+            .arg(
+                Arg::new("unwrap")
+                    .long("unwrap")
+                    .action(ArgAction::SetTrue)
+                    .conflicts_with("compress"),
+            )
+            .arg(Arg::new("compress").long("compress"))
+            .arg(
+                Arg::new("exclude")
+                    .long("exclude")
+                    .action(ArgAction::Append),
+            )
+            .arg(
+                Arg::new("long")
+                    .long("long")
+                    .num_args(0..=1)
+                    .require_equals(true),
+            )
+            .arg(Arg::new("config").long("config"))
+            .arg(Arg::new("target_dir"))
+    }
+
+    fn config(text: &str) -> Config {
+        Config {
+            path: PathBuf::from(FILE_NAME),
+            table: text.parse().unwrap(),
+        }
+    }
+
+    fn args(text: &str, profile: Option<&str>, argv: &[&str]) -> Result<Vec<String>, Error> {
+        let command = command();
+        let matches = command
+            .clone()
+            .try_get_matches_from(["wrap"].iter().chain(argv))
+            .unwrap();
+        Ok(config(text)
+            .args(profile, &command, &matches)?
+            .into_iter()
+            .map(|arg| arg.into_string().unwrap())
+            .collect())
+    }
+
+    #[test]
+    fn top_level_values_become_args() {
+        let text = r#"
+            compress = "zstd"
+            dry_run = true
+            verbose = false
+            exclude = ["target", "*.tmp"]
+            long = 27
+            target-dir = "/srv/data"
+        "#;
+        assert_eq!(
+            args(text, None, &[]).unwrap(),
+            [
+                "--compress=zstd",
+                "--dry-run",
+                "--exclude=target",
+                "--exclude=*.tmp",
+                "--long=27",
+                "/srv/data",
+            ]
+        );
+    }
+
+    #[test]
+    fn profile_overrides_top_level() {
+        let text = r#"
+            compress = "zstd"
+            [profile.fast]
+            compress = "gzip"
+        "#;
+        assert_eq!(args(text, None, &[]).unwrap(), ["--compress=zstd"]);
+        assert_eq!(args(text, Some("fast"), &[]).unwrap(), ["--compress=gzip"]);
+    }
+
+    #[test]
+    fn command_line_wins_over_file() {
+        let text = r#"compress = "zstd""#;
+        assert!(args(text, None, &["--compress", "xz"]).unwrap().is_empty());
+    }
+
+    #[test]
+    fn values_conflicting_with_the_command_line_are_dropped() {
+        let text = r#"compress = "zstd""#;
+        assert!(args(text, None, &["--unwrap"]).unwrap().is_empty());
+    }
+
+    #[test]
+    fn profile_drops_conflicting_top_level_values() {
+        let text = r#"
+            compress = "zstd"
+            verbose = true
+            [profile.restore]
+            unwrap = true
+        "#;
+        assert_eq!(
+            args(text, Some("restore"), &[]).unwrap(),
+            ["--unwrap", "--verbose"]
+        );
+    }
+
+    #[test]
+    fn disabled_flags_do_not_conflict() {
+        let text = r#"
+            compress = "zstd"
+            [profile.plain]
+            unwrap = false
+        "#;
+        assert_eq!(args(text, Some("plain"), &[]).unwrap(), ["--compress=zstd"]);
+    }
+
+    #[test]
+    fn rejects_unknown_and_reserved_keys() {
+        assert!(args("nope = true", None, &[]).is_err());
+        assert!(args(r#"config = "other.toml""#, None, &[]).is_err());
+    }
+
+    #[test]
+    fn rejects_missing_profile() {
+        assert!(args("verbose = true", Some("missing"), &[]).is_err());
+    }
+
+    #[test]
+    fn arg_values_checks_types() {
+        let command = command();
+        let arg = |id: &str| {
+            command
+                .get_arguments()
+                .find(|arg| arg.get_id() == id)
+                .unwrap()
+        };
+        let value = |text: &str| text.parse::<toml::Table>().unwrap()["v"].clone();
+
+        assert_eq!(
+            arg_values(arg("long"), &value("v = true")),
+            Ok(vec!["--long".into()])
+        );
+        assert_eq!(arg_values(arg("long"), &value("v = false")), Ok(Vec::new()));
+        assert_eq!(
+            arg_values(arg("compress"), &value("v = 1.5")),
+            Ok(vec!["--compress=1.5".into()])
+        );
+        assert!(arg_values(arg("verbose"), &value(r#"v = "yes""#)).is_err());
+        assert!(arg_values(arg("compress"), &value("v = [1]")).is_err());
+        assert!(arg_values(arg("exclude"), &value("v = [true]")).is_err());
+        assert!(arg_values(arg("target_dir"), &value("v = true")).is_err());
+    }
+}
@@ -0,0 +1,42 @@
+use clap::ValueEnum;
+use std::path::Path;
+
+/// What to do when the archive for a folder already exists
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum OnConflict {
+    /// Replace the existing archive
+    #[default]
+    Overwrite,
+    /// Leave the existing archive and its folder alone
+    Skip,
+    /// Write the new archive alongside the existing one with a numeric suffix (foo-1.tar, foo-2.tar, ...)
+    Rename,
+    /// Fail the folder, leaving the existing archive and the folder alone
+    Fail,
+}
+
+/// First `{stem}-{n}.{extension}` name for `tarball_name` that does not exist in `current_dir`
+pub(crate) fn renamed(current_dir: &Path, tarball_name: &str, extension: &str) -> String {
+    let stem = tarball_name
+        .strip_suffix(extension)
+        .and_then(|stem| stem.strip_suffix('.'))
+        .unwrap_or(tarball_name);
+    (1..)
+        .map(|n| format!("{}-{}.{}", stem, n, extension))
+        .find(|name| !current_dir.join(name).exists())
+        .unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn renames_to_the_first_free_suffix() {
+        let dir = tempfile::tempdir().unwrap();
+        assert_eq!(renamed(dir.path(), "foo.tar.gz", "tar.gz"), "foo-1.tar.gz");
+        std::fs::write(dir.path().join("foo-1.tar.gz"), "").unwrap();
+        std::fs::write(dir.path().join("foo-3.tar.gz"), "").unwrap();
+        assert_eq!(renamed(dir.path(), "foo.tar.gz", "tar.gz"), "foo-2.tar.gz");
+    }
+}
@@ -0,0 +1,158 @@
+use std::fmt;
+use std::path::PathBuf;
+
+/// Everything that can go wrong while wrapping or unwrapping folders
+#[derive(Debug)]
+pub enum Error {
+    /// The options given are invalid or inconsistent
+    Options(String),
+    /// The config file could not be read, or has an invalid value in it
+    Config { path: PathBuf, message: String },
+    /// A directory could not be searched for folders or archives
+    Scan {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    /// The output directory could not be created
+    OutputDir {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    /// The output directory does not have room for the archives
+    Space {
+        path: PathBuf,
+        needed: u64,
+        available: u64,
+    },
+    /// The archive for a folder already exists and `--on-conflict fail` was chosen
+    Conflict { archive: PathBuf },
+    /// An archive could not be written
+    Archive {
+        folder: PathBuf,
+        archive: PathBuf,
+        source: std::io::Error,
+    },
+    /// An archive does not match the folder it was created from (or could not be read back)
+    Verify {
+        folder: PathBuf,
+        archive: PathBuf,
+        mismatches: Vec<String>,
+    },
+    /// An archive could not be extracted
+    Extract {
+        archive: PathBuf,
+        folder: PathBuf,
+        source: std::io::Error,
+    },
+    /// Several archives would be extracted into the same folder
+    Ambiguous {
+        folder: PathBuf,
+        archives: Vec<PathBuf>,
+    },
+    /// A folder (or an archive in unwrap mode) could not be removed
+    Remove {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    /// The checksum manifest could not be written
+    Manifest {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Options(message) => write!(f, "{}", message),
+            Error::Config { path, message } => write!(f, "{:?}: {}", path, message),
+            Error::Scan { path, source } => write!(f, "could not read {:?}: {}", path, source),
+            Error::OutputDir { path, source } => {
+                write!(
+                    f,
+                    "could not create output directory {:?}: {}",
+                    path, source
+                )
+            }
+            Error::Space {
+                path,
+                needed,
+                available,
+            } => write!(
+                f,
+                "not enough space in {:?}: the folders hold {} bytes but only {} bytes are free",
+                path, needed, available
+            ),
+            Error::Conflict { archive } => write!(f, "{:?} already exists", archive),
+            Error::Archive {
+                folder,
+                archive,
+                source,
+            } => write!(
+                f,
+                "could not archive {:?} into {:?}: {}",
+                folder, archive, source
+            ),
+            Error::Verify {
+                folder,
+                archive,
+                mismatches,
+            } => write!(
+                f,
+                "{:?} does not match {:?}: {}",
+                archive,
+                folder,
+                mismatches.join(", ")
+            ),
+            Error::Extract {
+                archive,
+                folder,
+                source,
+            } => write!(
+                f,
+                "could not extract {:?} into {:?}: {}",
+                archive, folder, source
+            ),
+            Error::Ambiguous { folder, archives } => write!(
+                f,
+                "{:?} would be extracted from each of {:?}",
+                folder, archives
+            ),
+            Error::Remove { path, source } => {
+                write!(f, "could not remove {:?}: {}", path, source)
+            }
+            Error::Manifest { path, source } => {
+                write!(
+                    f,
+                    "could not write checksum manifest {:?}: {}",
+                    path, source
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Options(_)
+            | Error::Config { .. }
+            | Error::Space { .. }
+            | Error::Conflict { .. }
+            | Error::Verify { .. }
+            | Error::Ambiguous { .. } => None,
+            Error::Scan { source, .. }
+            | Error::OutputDir { source, .. }
+            | Error::Archive { source, .. }
+            | Error::Extract { source, .. }
+            | Error::Remove { source, .. }
+            | Error::Manifest { source, .. } => Some(source),
+        }
+    }
+}
+
+impl From<String> for Error {
+    fn from(message: String) -> Self {
+        Error::Options(message)
+    }
+}
@@ -0,0 +1,62 @@
+use crate::report::{serialize_path, FolderReport, Report};
+use serde::Serialize;
+use std::path::Path;
+
+/// Progress of a run, reported as it happens
+///
+/// Serialized with an `event` field naming the variant, so a stream of events can be written one JSON object
+/// per line.
+#[derive(Debug, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event<'a> {
+    /// The folders to archive have been found
+    Start { folders: usize },
+    /// A folder is about to be archived
+    FolderStart {
+        #[serde(serialize_with = "serialize_path")]
+        folder: &'a Path,
+        name: &'a str,
+    },
+    /// A folder has been archived (or skipped, or has failed)
+    FolderDone(&'a FolderReport),
+    /// The run is over
+    Report(&'a Report),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tags_each_event_with_its_name() {
+        let folder = FolderReport::new("a".into(), "a.tar".to_string(), "a.tar".into());
+        let report = Report::default();
+        let events = [
+            Event::Start { folders: 1 },
+            Event::FolderStart {
+                folder: Path::new("a"),
+                name: "a.tar",
+            },
+            Event::FolderDone(&folder),
+            Event::Report(&report),
+        ];
+        let lines = events
+            .iter()
+            .map(|event| serde_json::to_string(event).unwrap())
+            .collect::<Vec<_>>();
+        // one object per line
+        assert!(lines.iter().all(|line| !line.contains('\n')));
+        let json = lines
+            .iter()
+            .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap())
+            .collect::<Vec<_>>();
+        let tags = json.iter().map(|event| &event["event"]).collect::<Vec<_>>();
+        assert_eq!(tags, ["start", "folder_start", "folder_done", "report"]);
+        assert_eq!(json[0]["folders"], 1);
+        assert_eq!(json[1]["folder"], "a");
+        assert_eq!(json[1]["name"], "a.tar");
+        // reports are flattened into the event
+        assert_eq!(json[2]["status"], "dry_run");
+        assert!(json[3]["folders"].is_array());
+    }
+}
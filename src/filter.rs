@@ -0,0 +1,156 @@
+use crate::walk::{is_cache_dir, is_vcs};
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use regex::RegexSet;
+use std::path::Path;
+
+/// Patterns matched against folder names
+enum Patterns {
+    Glob(GlobSet),
+    Regex(RegexSet),
+}
+
+impl Patterns {
+    fn new(patterns: &[String], regex: bool) -> Result<Option<Self>, String> {
+        if patterns.is_empty() {
+            return Ok(None);
+        }
+        match regex {
+            true => RegexSet::new(patterns)
+                .map(|set| Some(Patterns::Regex(set)))
+                .map_err(|e| format!("invalid regex: {}", e)),
+            false => {
+                let mut builder = GlobSetBuilder::new();
+                for pattern in patterns {
+                    builder.add(
+                        Glob::new(pattern)
+                            .map_err(|e| format!("invalid glob {:?}: {}", pattern, e))?,
+                    );
+                }
+                builder
+                    .build()
+                    .map(|set| Some(Patterns::Glob(set)))
+                    .map_err(|e| format!("invalid glob: {}", e))
+            }
+        }
+    }
+
+    fn is_match(&self, name: &str) -> bool {
+        match self {
+            Patterns::Glob(set) => set.is_match(name),
+            Patterns::Regex(set) => set.is_match(name),
+        }
+    }
+}
+
+/// Decides which folders are tarballed from include and exclude patterns matched against folder names
+pub struct FolderFilter {
+    include: Option<Patterns>,
+    exclude: Option<Patterns>,
+    /// Whether folders tagged with a CACHEDIR.TAG are excluded
+    caches: bool,
+    /// Whether version control metadata folders are excluded
+    vcs: bool,
+}
+
+impl FolderFilter {
+    /// Builds a filter from glob patterns, or from regular expressions if `regex` is set
+    pub fn new(
+        include: &[String],
+        exclude: &[String],
+        regex: bool,
+        caches: bool,
+        vcs: bool,
+    ) -> Result<Self, String> {
+        Ok(FolderFilter {
+            include: Patterns::new(include, regex)?,
+            exclude: Patterns::new(exclude, regex)?,
+            caches,
+            vcs,
+        })
+    }
+
+    /// Whether a folder is excluded (and should be neither tarballed nor searched)
+    pub fn is_excluded(&self, path: &Path) -> bool {
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        (self.vcs && is_vcs(path))
+            || (self.caches && is_cache_dir(path))
+            || self
+                .exclude
+                .as_ref()
+                .is_some_and(|exclude| exclude.is_match(&name))
+    }
+
+    /// Whether a folder matches an include pattern (always true if there are none)
+    pub fn is_included(&self, name: &str) -> bool {
+        self.include
+            .as_ref()
+            .is_none_or(|include| include.is_match(name))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn filter(include: &[&str], exclude: &[&str], regex: bool) -> FolderFilter {
+        let strings =
+            |patterns: &[&str]| patterns.iter().map(|p| p.to_string()).collect::<Vec<_>>();
+        FolderFilter::new(&strings(include), &strings(exclude), regex, false, false).unwrap()
+    }
+
+    #[test]
+    fn includes_everything_without_patterns() {
+        let filter = filter(&[], &[], false);
+        assert!(filter.is_included("anything"));
+        assert!(!filter.is_excluded(Path::new("/tmp/anything")));
+    }
+
+    #[test]
+    fn matches_globs_against_folder_names() {
+        let filter = filter(&["proj-*", "docs"], &["*-old"], false);
+        assert!(filter.is_included("proj-a"));
+        assert!(filter.is_included("docs"));
+        assert!(!filter.is_included("my-proj-a"));
+        assert!(filter.is_excluded(Path::new("/tmp/proj-old")));
+        // only the name is matched, not the rest of the path
+        assert!(!filter.is_excluded(Path::new("/tmp/x-old/proj")));
+    }
+
+    #[test]
+    fn matches_regexes_against_folder_names() {
+        let filter = filter(&[r"^proj-\d+$"], &["tmp"], true);
+        assert!(filter.is_included("proj-12"));
+        assert!(!filter.is_included("proj-x"));
+        // regexes are unanchored unless asked
+        assert!(filter.is_excluded(Path::new("/a/my-tmp-dir")));
+    }
+
+    #[test]
+    fn rejects_invalid_patterns() {
+        let invalid = |pattern: &str, regex| {
+            FolderFilter::new(&[pattern.to_string()], &[], regex, false, false).is_err()
+        };
+        assert!(invalid("a[", false));
+        assert!(invalid("(", true));
+    }
+
+    #[test]
+    fn excludes_cache_and_vcs_folders() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = dir.path().join("cache");
+        std::fs::create_dir(&cache).unwrap();
+        std::fs::write(
+            cache.join("CACHEDIR.TAG"),
+            "Signature: 8a477f597d28d172789f06886806bc55",
+        )
+        .unwrap();
+        let filter = FolderFilter::new(&[], &[], false, true, true).unwrap();
+        assert!(filter.is_excluded(&cache));
+        assert!(filter.is_excluded(&dir.path().join(".git")));
+        assert!(!filter.is_excluded(dir.path()));
+        // neither is skipped unless asked
+        let filter = FolderFilter::new(&[], &[], false, false, false).unwrap();
+        assert!(!filter.is_excluded(&cache));
+        assert!(!filter.is_excluded(&dir.path().join(".git")));
+    }
+}
@@ -0,0 +1,30 @@
+use crate::compression::{Compression, Settings};
+use clap::ValueEnum;
+
+/// Archive formats that folders can be packed into
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum Format {
+    /// Tarball, optionally compressed with --compress
+    #[default]
+    Tar,
+    /// Zip archive with stored or deflated entries
+    Zip,
+}
+
+impl Format {
+    /// File extension (without the leading dot) for an archive of this format
+    pub fn extension(&self, compression: Compression) -> &'static str {
+        match self {
+            Format::Tar => compression.extension(),
+            Format::Zip => "zip",
+        }
+    }
+
+    /// Checks that the compression settings can be used with this format
+    pub fn validate(&self, compression: &Settings) -> Result<(), String> {
+        match self {
+            Format::Tar => Ok(()),
+            Format::Zip => crate::zipper::validate(compression),
+        }
+    }
+}
@@ -0,0 +1,686 @@
+use crate::checksum::Algorithm;
+use crate::compression::Settings;
+use crate::conflict::OnConflict;
+use crate::error::Error;
+use crate::events::Event;
+use crate::filter::FolderFilter;
+use crate::format::Format;
+use crate::output::{say, Messages};
+use crate::pathfinder::pathfinder;
+use crate::report::{FolderReport, Report};
+use crate::root::ArchiveRoot;
+use crate::space::check_space;
+use crate::tarballer::tarballer;
+use crate::template::NameTemplate;
+use crate::untarballer::{archive_finder, untarballer};
+use crate::walk::Excludes;
+use std::collections::BTreeMap;
+use std::path::{Component, Path, PathBuf};
+
+/// Everything that controls how folders are selected, archived and removed
+///
+/// Start from `Options::default()` - More options may be added in any release.
+#[derive(Clone, Debug)]
+#[non_exhaustive]
+pub struct Options {
+    /// Print verbose output
+    pub verbose: bool,
+    /// Remove folders after archiving them (only once the archive has been verified)
+    pub remove: bool,
+    /// List what would be done without writing or removing anything
+    pub dry_run: bool,
+    /// Verify archives against their folders even when not removing them
+    pub verify: bool,
+    /// Write a checksum manifest listing every archive created
+    pub checksum: Option<Algorithm>,
+    /// Archive folders this many levels below the target directory
+    pub depth: usize,
+    /// Also archive folders without subfolders that are shallower than `depth` but at least this deep
+    pub min_depth: Option<usize>,
+    /// Only archive folders whose name matches one of these patterns
+    pub include: Vec<String>,
+    /// Never archive or search folders whose name matches one of these patterns
+    pub exclude: Vec<String>,
+    /// Treat `include` and `exclude` as regular expressions instead of globs
+    pub regex: bool,
+    /// .gitignore style patterns for files left out of archives
+    pub exclude_from_archive: Vec<String>,
+    /// Honour .gitignore files inside folders (.wrapignore files always are) - On by default
+    pub gitignore: bool,
+    /// Skip directories tagged with a CACHEDIR.TAG
+    pub exclude_caches: bool,
+    /// Skip version control metadata
+    pub exclude_vcs: bool,
+    /// What to do when the archive for a folder already exists
+    pub on_conflict: OnConflict,
+    /// Never prompt when a folder cannot be removed, retry with backoff instead (always the case when
+    /// `messages` has no prompt)
+    pub no_prompt: bool,
+    /// How many times removing a busy folder is retried without prompting before giving up on it
+    pub retries: u32,
+    /// Delay before the first retry, doubled after each one
+    pub backoff_ms: u64,
+    /// Carry on with the remaining folders after one fails instead of stopping
+    pub keep_going: bool,
+    /// Make archives reproducible: sort entries, clamp mtimes to this many seconds since the Unix epoch,
+    /// drop ownership and normalise permissions to 755 or 644
+    pub reproducible: Option<u64>,
+    /// Number of folders archived in parallel - 0 uses one job per CPU
+    pub jobs: usize,
+    pub format: Format,
+    pub compression: Settings,
+    /// Where entries are stored inside each archive
+    pub archive_root: ArchiveRoot,
+    /// Write archives (and the checksum manifest) here instead of next to their folders, mirroring the folder
+    /// tree below the target directory
+    pub output_dir: Option<PathBuf>,
+    /// Template archive names are made from, before the extension
+    pub name_template: NameTemplate,
+    /// Where progress, errors and prompts go - Nowhere by default
+    pub messages: Messages,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            verbose: false,
+            remove: false,
+            dry_run: false,
+            verify: false,
+            checksum: None,
+            depth: 1,
+            min_depth: None,
+            include: Vec::new(),
+            exclude: Vec::new(),
+            regex: false,
+            exclude_from_archive: Vec::new(),
+            gitignore: true,
+            exclude_caches: false,
+            exclude_vcs: false,
+            on_conflict: OnConflict::default(),
+            no_prompt: false,
+            retries: 3,
+            backoff_ms: 500,
+            keep_going: false,
+            reproducible: None,
+            jobs: 1,
+            format: Format::default(),
+            compression: Settings::default(),
+            archive_root: ArchiveRoot::default(),
+            output_dir: None,
+            name_template: NameTemplate::default(),
+            messages: Messages::default(),
+        }
+    }
+}
+
+/// Archives the folders in a target directory
+///
+/// Built with `WrapJob::new` and the option setters, then either inspected with `plan` or carried out with `run`.
+#[derive(Clone, Debug)]
+pub struct WrapJob {
+    target_dir: PathBuf,
+    options: Options,
+}
+
+impl WrapJob {
+    /// Starts a job for the folders in `target_dir` with default options
+    pub fn new(target_dir: impl Into<PathBuf>) -> Self {
+        WrapJob::with_options(target_dir, Options::default())
+    }
+
+    /// Starts a job for the folders in `target_dir` with the given options
+    pub fn with_options(target_dir: impl Into<PathBuf>, options: Options) -> Self {
+        WrapJob {
+            target_dir: target_dir.into(),
+            options,
+        }
+    }
+
+    pub fn target_dir(&self) -> &Path {
+        &self.target_dir
+    }
+
+    pub fn options(&self) -> &Options {
+        &self.options
+    }
+
+    pub fn verbose(mut self, verbose: bool) -> Self {
+        self.options.verbose = verbose;
+        self
+    }
+
+    pub fn messages(mut self, messages: Messages) -> Self {
+        self.options.messages = messages;
+        self
+    }
+
+    pub fn remove(mut self, remove: bool) -> Self {
+        self.options.remove = remove;
+        self
+    }
+
+    pub fn dry_run(mut self, dry_run: bool) -> Self {
+        self.options.dry_run = dry_run;
+        self
+    }
+
+    pub fn verify(mut self, verify: bool) -> Self {
+        self.options.verify = verify;
+        self
+    }
+
+    pub fn checksum(mut self, checksum: Option<Algorithm>) -> Self {
+        self.options.checksum = checksum;
+        self
+    }
+
+    pub fn depth(mut self, depth: usize) -> Self {
+        self.options.depth = depth;
+        self
+    }
+
+    pub fn min_depth(mut self, min_depth: Option<usize>) -> Self {
+        self.options.min_depth = min_depth;
+        self
+    }
+
+    /// Adds a pattern folder names must match to be archived
+    pub fn include(mut self, pattern: impl Into<String>) -> Self {
+        self.options.include.push(pattern.into());
+        self
+    }
+
+    /// Adds a pattern for folder names that are never archived or searched
+    pub fn exclude(mut self, pattern: impl Into<String>) -> Self {
+        self.options.exclude.push(pattern.into());
+        self
+    }
+
+    pub fn regex(mut self, regex: bool) -> Self {
+        self.options.regex = regex;
+        self
+    }
+
+    /// Adds a .gitignore style pattern for files left out of archives
+    pub fn exclude_from_archive(mut self, pattern: impl Into<String>) -> Self {
+        self.options.exclude_from_archive.push(pattern.into());
+        self
+    }
+
+    pub fn gitignore(mut self, gitignore: bool) -> Self {
+        self.options.gitignore = gitignore;
+        self
+    }
+
+    pub fn exclude_caches(mut self, exclude_caches: bool) -> Self {
+        self.options.exclude_caches = exclude_caches;
+        self
+    }
+
+    pub fn exclude_vcs(mut self, exclude_vcs: bool) -> Self {
+        self.options.exclude_vcs = exclude_vcs;
+        self
+    }
+
+    pub fn on_conflict(mut self, on_conflict: OnConflict) -> Self {
+        self.options.on_conflict = on_conflict;
+        self
+    }
+
+    pub fn no_prompt(mut self, no_prompt: bool) -> Self {
+        self.options.no_prompt = no_prompt;
+        self
+    }
+
+    pub fn retries(mut self, retries: u32) -> Self {
+        self.options.retries = retries;
+        self
+    }
+
+    pub fn backoff_ms(mut self, backoff_ms: u64) -> Self {
+        self.options.backoff_ms = backoff_ms;
+        self
+    }
+
+    pub fn keep_going(mut self, keep_going: bool) -> Self {
+        self.options.keep_going = keep_going;
+        self
+    }
+
+    pub fn reproducible(mut self, reproducible: Option<u64>) -> Self {
+        self.options.reproducible = reproducible;
+        self
+    }
+
+    pub fn jobs(mut self, jobs: usize) -> Self {
+        self.options.jobs = jobs;
+        self
+    }
+
+    pub fn format(mut self, format: Format) -> Self {
+        self.options.format = format;
+        self
+    }
+
+    pub fn compression(mut self, compression: Settings) -> Self {
+        self.options.compression = compression;
+        self
+    }
+
+    pub fn archive_root(mut self, archive_root: ArchiveRoot) -> Self {
+        self.options.archive_root = archive_root;
+        self
+    }
+
+    pub fn output_dir(mut self, output_dir: Option<PathBuf>) -> Self {
+        self.options.output_dir = output_dir;
+        self
+    }
+
+    pub fn name_template(mut self, name_template: NameTemplate) -> Self {
+        self.options.name_template = name_template;
+        self
+    }
+
+    /// Checks that the options are consistent and every pattern is valid
+    pub fn validate(&self) -> Result<(), Error> {
+        self.prepare().map(|_| ())
+    }
+
+    /// Lists the folders that would be archived, mapped to the archive each one would be written to
+    ///
+    /// Fails if any folder could not be searched or named, even with `keep_going` set.
+    pub fn plan(&self) -> Result<BTreeMap<PathBuf, PathBuf>, Error> {
+        let (filter, excludes) = self.prepare()?;
+        let (tarball_names_and_paths, failed) = self.pathfinder(&filter, &excludes)?;
+        if let Some(e) = failed.into_iter().find_map(|report| report.error) {
+            return Err(e);
+        }
+        Ok(tarball_names_and_paths
+            .into_iter()
+            .map(|(tarball_name, folder_path)| (folder_path, self.archive_dir().join(tarball_name)))
+            .collect())
+    }
+
+    /// Archives (and optionally verifies and removes) every selected folder
+    ///
+    /// Errors are only returned when the run as a whole could not go ahead (invalid options, an unreadable
+    /// target directory or output directory) - Folders that failed, and a checksum manifest that could not be
+    /// written, are marked as such in the report.
+    pub fn run(&self) -> Result<Report, Error> {
+        self.run_with_events(|_| {})
+    }
+
+    /// Same as `run`, calling `on_event` as the run progresses
+    ///
+    /// With more than one job, folder events come from several threads at once.
+    pub fn run_with_events(&self, on_event: impl Fn(&Event) + Sync) -> Result<Report, Error> {
+        let options = &self.options;
+        let (filter, excludes) = self.prepare()?;
+        if let Some(output_dir) = &options.output_dir {
+            match (options.dry_run, output_dir.exists()) {
+                (true, false) => {
+                    say!(
+                        options.messages,
+                        "Dry run - would create output directory: {:?}",
+                        output_dir
+                    )
+                }
+                (false, false) => {
+                    if options.verbose {
+                        say!(
+                            options.messages,
+                            "Creating output directory: {:?}",
+                            output_dir
+                        );
+                    }
+                    std::fs::create_dir_all(output_dir).map_err(|source| Error::OutputDir {
+                        path: output_dir.clone(),
+                        source,
+                    })?;
+                }
+                (_, true) => {}
+            }
+        }
+        let (tarball_names_and_paths, failed) = self.pathfinder(&filter, &excludes)?;
+        if let Some(output_dir) = &options.output_dir {
+            if !options.dry_run {
+                check_space(options, &excludes, &tarball_names_and_paths, output_dir)?;
+            }
+        }
+        on_event(&Event::Start {
+            folders: tarball_names_and_paths.len() + failed.len(),
+        });
+        for report in &failed {
+            on_event(&Event::FolderStart {
+                folder: &report.folder,
+                name: &report.name,
+            });
+            on_event(&Event::FolderDone(report));
+        }
+        let mut report = tarballer(
+            options,
+            &excludes,
+            tarball_names_and_paths,
+            self.archive_dir(),
+            &on_event,
+        );
+        // folders that could not even be searched are reported alongside the rest
+        report.folders.extend(failed);
+        report.folders.sort_by(|a, b| a.folder.cmp(&b.folder));
+        on_event(&Event::Report(&report));
+        Ok(report)
+    }
+
+    /// Directory archives are written to - The output directory if there is one, otherwise the target directory
+    fn archive_dir(&self) -> &Path {
+        self.options
+            .output_dir
+            .as_deref()
+            .unwrap_or(&self.target_dir)
+    }
+
+    /// Validates the options and builds the folder filter and archive exclusions from them
+    fn prepare(&self) -> Result<(FolderFilter, Excludes), Error> {
+        let options = &self.options;
+        options.compression.validate()?;
+        options.format.validate(&options.compression)?;
+        if options.depth == 0 {
+            return Err(Error::Options("depth must be at least 1".to_string()));
+        }
+        match options.min_depth {
+            Some(0) => return Err(Error::Options("min depth must be at least 1".to_string())),
+            Some(min_depth) if min_depth > options.depth => {
+                return Err(Error::Options(format!(
+                    "--min-depth {} is deeper than --depth {}",
+                    min_depth, options.depth
+                )))
+            }
+            _ => {}
+        }
+        let filter = FolderFilter::new(
+            &options.include,
+            &options.exclude,
+            options.regex,
+            options.exclude_caches,
+            options.exclude_vcs,
+        )?;
+        let excludes = Excludes::new(
+            &options.exclude_from_archive,
+            options.gitignore,
+            options.exclude_caches,
+            options.exclude_vcs,
+        )?
+        .with_messages(options.messages.clone());
+        Ok((filter, excludes))
+    }
+
+    fn pathfinder(
+        &self,
+        filter: &FolderFilter,
+        excludes: &Excludes,
+    ) -> Result<
+        (
+            std::collections::HashMap<String, PathBuf>,
+            Vec<FolderReport>,
+        ),
+        Error,
+    > {
+        let options = &self.options;
+        let (mut tarball_names_and_paths, failed) =
+            pathfinder(options, &self.target_dir, filter, excludes)?;
+
+        // never archive the output directory, or a folder it is inside, into itself - The output directory may
+        // not exist yet in a dry run
+        if let Some(output_dir) = options.output_dir.as_deref().and_then(resolve) {
+            tarball_names_and_paths.retain(|_, folder_path| {
+                let contains_output = folder_path
+                    .canonicalize()
+                    .is_ok_and(|folder_path| output_dir.starts_with(folder_path));
+                if contains_output && options.verbose {
+                    say!(
+                        options.messages,
+                        "Skipping folder holding the output directory: {:?}",
+                        folder_path
+                    );
+                }
+                !contains_output
+            });
+        }
+        Ok((tarball_names_and_paths, failed))
+    }
+}
+
+/// Extracts the archives in a target directory, each into a folder named after it
+///
+/// Built with `UnwrapJob::new` and the option setters, then either inspected with `plan` or carried out with
+/// `run`. Only `verbose`, `remove`, `dry_run`, `keep_going`, `archive_root` and `messages` apply to unwrapping.
+#[derive(Clone, Debug)]
+pub struct UnwrapJob {
+    target_dir: PathBuf,
+    options: Options,
+}
+
+impl UnwrapJob {
+    /// Starts a job for the archives in `target_dir` with default options
+    pub fn new(target_dir: impl Into<PathBuf>) -> Self {
+        UnwrapJob::with_options(target_dir, Options::default())
+    }
+
+    /// Starts a job for the archives in `target_dir` with the given options
+    pub fn with_options(target_dir: impl Into<PathBuf>, options: Options) -> Self {
+        UnwrapJob {
+            target_dir: target_dir.into(),
+            options,
+        }
+    }
+
+    pub fn target_dir(&self) -> &Path {
+        &self.target_dir
+    }
+
+    pub fn options(&self) -> &Options {
+        &self.options
+    }
+
+    pub fn verbose(mut self, verbose: bool) -> Self {
+        self.options.verbose = verbose;
+        self
+    }
+
+    pub fn messages(mut self, messages: Messages) -> Self {
+        self.options.messages = messages;
+        self
+    }
+
+    pub fn remove(mut self, remove: bool) -> Self {
+        self.options.remove = remove;
+        self
+    }
+
+    pub fn dry_run(mut self, dry_run: bool) -> Self {
+        self.options.dry_run = dry_run;
+        self
+    }
+
+    pub fn keep_going(mut self, keep_going: bool) -> Self {
+        self.options.keep_going = keep_going;
+        self
+    }
+
+    pub fn archive_root(mut self, archive_root: ArchiveRoot) -> Self {
+        self.options.archive_root = archive_root;
+        self
+    }
+
+    /// Lists the archives that would be extracted, mapped to the folder each one would be extracted into
+    ///
+    /// Fails if two archives would be extracted into the same folder.
+    pub fn plan(&self) -> Result<BTreeMap<PathBuf, PathBuf>, Error> {
+        let mut plan = BTreeMap::new();
+        for (folder_name, archives) in archive_finder(&self.options, &self.target_dir)? {
+            let folder_path = self.target_dir.join(folder_name);
+            match <[_; 1]>::try_from(archives) {
+                Ok([archive]) => {
+                    plan.insert(archive.path, folder_path);
+                }
+                Err(archives) => {
+                    return Err(Error::Ambiguous {
+                        folder: folder_path,
+                        archives: archives.into_iter().map(|archive| archive.path).collect(),
+                    })
+                }
+            }
+        }
+        Ok(plan)
+    }
+
+    /// Extracts (and optionally removes) every archive
+    ///
+    /// Errors are only returned when the target directory could not be read - Archives that failed are marked
+    /// as such in the report.
+    pub fn run(&self) -> Result<Report, Error> {
+        self.run_with_events(|_| {})
+    }
+
+    /// Same as `run`, calling `on_event` as the run progresses
+    pub fn run_with_events(&self, on_event: impl Fn(&Event)) -> Result<Report, Error> {
+        let archives = archive_finder(&self.options, &self.target_dir)?;
+        Ok(untarballer(
+            &self.options,
+            archives,
+            &self.target_dir,
+            &on_event,
+        ))
+    }
+}
+
+/// Absolute form of `path` with symlinks resolved as far as it exists, and the rest normalised lexically
+fn resolve(path: &Path) -> Option<PathBuf> {
+    let mut normalized = PathBuf::new();
+    for component in std::path::absolute(path).ok()?.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                normalized.pop();
+            }
+            component => normalized.push(component),
+        }
+    }
+    let mut missing = Vec::new();
+    let mut existing = normalized.as_path();
+    loop {
+        if let Ok(canonical) = existing.canonicalize() {
+            return Some(
+                missing
+                    .into_iter()
+                    .rev()
+                    .fold(canonical, |path, part| path.join(part)),
+            );
+        }
+        missing.push(existing.file_name()?);
+        existing = existing.parent()?;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resolves_paths_that_do_not_exist_yet() {
+        let dir = tempfile::tempdir().unwrap();
+        let canonical = dir.path().canonicalize().unwrap();
+        std::fs::create_dir(dir.path().join("a")).unwrap();
+        assert_eq!(resolve(&dir.path().join("a")), Some(canonical.join("a")));
+        assert_eq!(
+            resolve(&dir.path().join("a/nested/../new/./out")),
+            Some(canonical.join("a/new/out"))
+        );
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn folders_that_cannot_be_named_fail_on_their_own() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(dir.path().join("a")).unwrap();
+        std::fs::create_dir_all(dir.path().join("b")).unwrap();
+        // {hash8} cannot read through a dangling symlink
+        std::os::unix::fs::symlink("missing", dir.path().join("b/broken")).unwrap();
+        let job = WrapJob::new(dir.path())
+            .name_template("{name}-{hash8}".parse().unwrap())
+            .dry_run(true);
+        assert!(matches!(job.run(), Err(Error::Scan { .. })));
+
+        let report = job.keep_going(true).run().unwrap();
+        let statuses = report
+            .folders
+            .iter()
+            .map(|folder| {
+                (
+                    folder.folder.strip_prefix(dir.path()).unwrap(),
+                    folder.status,
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            statuses,
+            [
+                (Path::new("a"), crate::Status::DryRun),
+                (Path::new("b"), crate::Status::Failed),
+            ]
+        );
+    }
+
+    #[test]
+    fn sends_messages_to_the_sink() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir(dir.path().join("a")).unwrap();
+        let lines = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+        let sink = lines.clone();
+        let messages = Messages::new(move |level, line: &str| {
+            sink.lock().unwrap().push((level, line.to_string()))
+        });
+        WrapJob::new(dir.path())
+            .dry_run(true)
+            .messages(messages)
+            .run()
+            .unwrap();
+        let lines = lines.lock().unwrap();
+        assert!(lines.contains(&(
+            crate::Level::Info,
+            format!("Dry run - would tarball folder: {:?}", dir.path().join("a"))
+        )));
+    }
+
+    #[test]
+    fn unwraps_archives_into_folders() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir(dir.path().join("a")).unwrap();
+        std::fs::write(dir.path().join("a/f"), "hello").unwrap();
+        WrapJob::new(dir.path()).remove(true).run().unwrap();
+        assert!(!dir.path().join("a").exists());
+
+        let job = UnwrapJob::new(dir.path()).remove(true);
+        assert_eq!(
+            job.plan().unwrap(),
+            BTreeMap::from([(dir.path().join("a.tar"), dir.path().join("a"))])
+        );
+        let report = job.run().unwrap();
+        assert_eq!(report.count(crate::Status::Archived), 1);
+        assert_eq!(std::fs::read(dir.path().join("a/f")).unwrap(), b"hello");
+        assert!(!dir.path().join("a.tar").exists());
+    }
+
+    #[test]
+    fn cannot_plan_archives_sharing_a_folder() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("a.tar"), "").unwrap();
+        std::fs::write(dir.path().join("a.zip"), "").unwrap();
+        assert!(matches!(
+            UnwrapJob::new(dir.path()).plan(),
+            Err(Error::Ambiguous { .. })
+        ));
+    }
+}
@@ -0,0 +1,35 @@
+//! Creates tarballs (or zip archives) from the folders in a directory and optionally removes the folders
+//! that created them.
+//!
+//! A run is described by a [`WrapJob`]: create one for a target directory, adjust its [`Options`] with the
+//! builder methods, then call [`WrapJob::plan`] to see which folders would be archived where, or
+//! [`WrapJob::run`] to archive them and get a [`Report`] back. [`UnwrapJob`] does the same for extracting
+//! archives. Nothing is printed unless a [`Messages`] sink is given. The `wrap` binary is a thin command line
+//! interface over this.
+
+pub mod checksum;
+pub mod compression;
+pub mod config;
+pub mod conflict;
+mod error;
+pub mod events;
+pub mod filter;
+pub mod format;
+mod job;
+mod output;
+mod pathfinder;
+mod report;
+pub mod root;
+mod space;
+mod tarball;
+mod tarballer;
+pub mod template;
+mod untarballer;
+mod verify;
+pub mod walk;
+mod zipper;
+
+pub use error::Error;
+pub use job::{Options, UnwrapJob, WrapJob};
+pub use output::{Level, Messages};
+pub use report::{FolderReport, Report, Status};
@@ -1,222 +1,544 @@
-use clap::Parser;
-use std::fs::File;
-use std::path::Path;
-use tar::Builder;
+use clap::builder::RangedU64ValueParser;
+use clap::error::ErrorKind;
+use clap::{CommandFactory, FromArgMatches, Parser, ValueEnum};
+use std::io::{IsTerminal, Write};
+use std::path::PathBuf;
+use std::process::ExitCode;
+use wrap::checksum::Algorithm;
+use wrap::compression::{Compression, Settings};
+use wrap::config::{self, Config};
+use wrap::conflict::OnConflict;
+use wrap::events::Event;
+use wrap::format::Format;
+use wrap::root::ArchiveRoot;
+use wrap::template::NameTemplate;
+use wrap::{Level, Messages, Options, Report, Status, UnwrapJob, WrapJob};
 
 #[derive(Parser, Debug)]
 #[clap(author = "Maxwell Rupp", version, about)]
 /// Application configuration
 struct Args {
     /// Print Verbose output
-    #[arg(short = 'v')]
+    #[arg(short = 'v', long = "verbose", env = "WRAP_VERBOSE")]
     verbose: bool,
 
-    /// Remove folders after tarballing
-    #[arg(short = 'r', long = "remove")]
+    /// Remove folders after tarballing (or archives after extracting in unwrap mode)
+    #[arg(short = 'r', long = "remove", env = "WRAP_REMOVE")]
     remove: bool,
 
     /// Dry run - List folders to be tarballed but do not create tarballs
-    #[arg(short = 'd', long = "dry-run")]
+    #[arg(short = 'd', long = "dry-run", env = "WRAP_DRY_RUN")]
     dry_run: bool,
 
+    /// Unwrap mode - Extract every archive in the target directory into a folder named after it (combine with -r to remove the archives)
+    #[arg(short = 'u', long = "unwrap", env = "WRAP_UNWRAP", conflicts_with_all = ["format", "compress", "level", "long", "threads", "on_conflict", "reproducible", "output_dir", "name_template"])]
+    unwrap: bool,
+
+    /// Verify archives against their folders after creating them - Always done before removing folders with -r
+    #[arg(long = "verify", env = "WRAP_VERIFY")]
+    verify: bool,
+
+    /// Write a checksum manifest (SHA256SUMS or B3SUMS) listing every tarball created to the target directory
+    #[arg(
+        long = "checksum",
+        value_enum,
+        value_name = "ALGORITHM",
+        env = "WRAP_CHECKSUM"
+    )]
+    checksum: Option<Algorithm>,
+
+    /// Tarball folders this many levels below the target directory - Default is the target directory's immediate subfolders
+    #[arg(long = "depth", default_value_t = 1, value_parser = RangedU64ValueParser::<usize>::new().range(1..), env = "WRAP_DEPTH")]
+    depth: usize,
+
+    /// Also tarball folders without subfolders that are shallower than --depth but at least this deep - Default is --depth
+    #[arg(long = "min-depth", value_parser = RangedU64ValueParser::<usize>::new().range(1..), env = "WRAP_MIN_DEPTH")]
+    min_depth: Option<usize>,
+
+    /// Only tarball folders whose name matches this glob - Can be given multiple times
+    #[arg(
+        short = 'i',
+        long = "include",
+        value_name = "PATTERN",
+        env = "WRAP_INCLUDE"
+    )]
+    include: Vec<String>,
+
+    /// Never tarball or search folders whose name matches this glob - Can be given multiple times
+    #[arg(
+        short = 'e',
+        long = "exclude",
+        value_name = "PATTERN",
+        env = "WRAP_EXCLUDE"
+    )]
+    exclude: Vec<String>,
+
+    /// Treat --include and --exclude patterns as regular expressions instead of globs
+    #[arg(long = "regex", env = "WRAP_REGEX")]
+    regex: bool,
+
+    /// Leave files matching this .gitignore style pattern (e.g. '*.o' or 'target/') out of tarballs - Can be given multiple times
+    #[arg(
+        long = "exclude-from-archive",
+        value_name = "PATTERN",
+        env = "WRAP_EXCLUDE_FROM_ARCHIVE"
+    )]
+    exclude_from_archive: Vec<String>,
+
+    /// Keep files ignored by .gitignore files inside each folder in tarballs (.wrapignore files are always honoured) - Default is to leave them out
+    #[arg(long = "no-gitignore", env = "WRAP_NO_GITIGNORE")]
+    no_gitignore: bool,
+
+    /// Skip folders tagged with a valid CACHEDIR.TAG, and leave the contents of tagged directories out of tarballs
+    #[arg(long = "exclude-caches", env = "WRAP_EXCLUDE_CACHES")]
+    exclude_caches: bool,
+
+    /// Skip version control metadata (.git, .hg, .svn, ...) both as folders and inside tarballs
+    #[arg(long = "exclude-vcs", env = "WRAP_EXCLUDE_VCS")]
+    exclude_vcs: bool,
+
+    /// What to do when a folder's tarball already exists - rename appends a numeric suffix (foo-1.tar, foo-2.tar, ...)
+    #[arg(long = "on-conflict", value_enum, value_name = "ACTION", default_value_t = OnConflict::Overwrite, env = "WRAP_ON_CONFLICT")]
+    on_conflict: OnConflict,
+
+    /// Never wait for Enter when a folder is busy or cannot be removed, retry with backoff and then give up on it instead - Implied when stdin is not a terminal
+    #[arg(long = "no-prompt", env = "WRAP_NO_PROMPT")]
+    no_prompt: bool,
+
+    /// Number of times to retry removing a folder without prompting - Default is 3
+    #[arg(long = "retries", default_value_t = 3, env = "WRAP_RETRIES")]
+    retries: u32,
+
+    /// Milliseconds to wait before the first retry, doubled after each one - Default is 500
+    #[arg(
+        long = "backoff-ms",
+        value_name = "MS",
+        default_value_t = 500,
+        env = "WRAP_BACKOFF_MS"
+    )]
+    backoff_ms: u64,
+
+    /// Keep going after a folder fails (it is never removed) and print a table of every folder at the end
+    #[arg(short = 'k', long = "keep-going", env = "WRAP_KEEP_GOING")]
+    keep_going: bool,
+
+    /// Make byte-for-byte reproducible archives: clamp mtimes to TIMESTAMP (seconds since the Unix epoch), zero owners and normalise permissions to 755/644 - Default TIMESTAMP is $SOURCE_DATE_EPOCH, or 1980-01-01 if unset
+    #[arg(long = "reproducible", value_name = "TIMESTAMP", num_args = 0..=1, require_equals = true, env = "WRAP_REPRODUCIBLE")]
+    reproducible: Option<Option<u64>>,
+
+    /// Where entries are stored inside archives: folder (under the folder's name), none (at the top level) or custom:<prefix> - Also used to strip entries when unwrapping, where folder strips the one directory everything is under whatever its name - Default is folder
+    #[arg(long = "archive-root", value_name = "ROOT", default_value_t = ArchiveRoot::Folder, env = "WRAP_ARCHIVE_ROOT")]
+    archive_root: ArchiveRoot,
+
+    /// Write tarballs (and the checksum manifest) to this directory instead of next to their folders, mirroring the folder tree - Created if missing
+    #[arg(
+        short = 'o',
+        long = "output-dir",
+        value_name = "DIR",
+        env = "WRAP_OUTPUT_DIR"
+    )]
+    output_dir: Option<PathBuf>,
+
+    /// Name tarballs from this template (the extension is added) - Placeholders are {name}, {date}, {date:%Y%m%d}, {host}, {mtime}, {mtime:FORMAT}, {size}, {hash8} - Default is {name}
+    #[arg(long = "name-template", value_name = "TEMPLATE", default_value_t = NameTemplate::default(), env = "WRAP_NAME_TEMPLATE")]
+    name_template: NameTemplate,
+
+    /// Number of folders to tarball in parallel - 0 uses one job per CPU
+    #[arg(short = 'j', long = "jobs", default_value_t = 1, env = "WRAP_JOBS")]
+    jobs: usize,
+
+    /// Archive format to pack folders into
+    #[arg(short = 'f', long = "format", value_enum, default_value_t = Format::Tar, env = "WRAP_FORMAT")]
+    format: Format,
+
+    /// Compress tarballs with the given codec (zip archives support none or gzip, which selects deflate)
+    #[arg(short = 'c', long = "compress", value_enum, default_value_t = Compression::None, env = "WRAP_COMPRESS")]
+    compress: Compression,
+
+    /// Compression level - Range and default depend on the codec (gzip: 1-9, default 6; zstd: 1-22, default 3; xz: 0-9, default 6; bzip2: 1-9, default 9)
+    #[arg(short = 'l', long = "level", env = "WRAP_LEVEL")]
+    level: Option<u32>,
+
+    /// Enable zstd long-distance matching with the given window log (10-31) - Default window log is 27
+    #[arg(long = "long", value_name = "WINDOW_LOG", num_args = 0..=1, require_equals = true, default_missing_value = "27", env = "WRAP_LONG")]
+    long: Option<u32>,
+
+    /// Number of zstd worker threads - Default is single-threaded
+    #[arg(short = 'T', long = "threads", env = "WRAP_THREADS")]
+    threads: Option<u32>,
+
+    /// Format of the report printed once the run is over - json prints a report for every folder to stdout and sends all other output to stderr
+    #[arg(long = "output", value_enum, default_value_t = OutputFormat::Text, env = "WRAP_OUTPUT")]
+    output: OutputFormat,
+
+    /// Stream progress events to stdout as they happen, one JSON object per line, and send all other output to stderr
+    #[arg(
+        long = "events",
+        value_enum,
+        conflicts_with = "output",
+        env = "WRAP_EVENTS"
+    )]
+    events: Option<EventFormat>,
+
+    /// Read defaults from this wrap.toml instead of the one in the target directory or the user config directory ($XDG_CONFIG_HOME/wrap)
+    #[arg(long = "config", value_name = "FILE", env = "WRAP_CONFIG")]
+    config: Option<PathBuf>,
+
+    /// Apply the [profile.NAME] section of the config file on top of its defaults
+    #[arg(long = "profile", value_name = "NAME", env = "WRAP_PROFILE")]
+    profile: Option<String>,
+
     /// Target folder - Tarball folders in this directory - Default is current directory
-    #[arg()]
+    #[arg(env = "WRAP_TARGET_DIR")]
     target_dir: Option<String>,
 }
 
-fn main() {
-    let args = Args::parse();
-
-    let target_dir = target_dir_finder(args.target_dir);
-
-    let tarball_names_and_paths = pathfinder(args.verbose, target_dir);
+/// How the report is printed at the end of a run
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum OutputFormat {
+    /// Human readable messages (and a summary table with --keep-going)
+    Text,
+    /// A single JSON document
+    Json,
+}
 
-    tarballer(
-        args.dry_run,
-        args.verbose,
-        args.remove,
-        tarball_names_and_paths,
-        target_dir,
-    );
+/// How progress events are streamed
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum EventFormat {
+    /// Newline delimited JSON
+    Ndjson,
 }
 
-fn target_dir_finder(target_dir: Option<String>) -> &'static Path {
-    match target_dir {
-        Some(dir) => {
-            let path = Path::new(&dir);
-            if path.exists() {
-                Box::leak(Box::new(path.to_path_buf())).as_path()
-            } else {
-                panic!("Target directory does not exist: {:?}", dir);
-            }
-        }
-        None => {
-            // If no target directory is provided, use the current directory
-            Path::new(".")
-        }
+impl Args {
+    /// Whether stdout is reserved for JSON, leaving human readable output to stderr
+    fn machine_readable(&self) -> bool {
+        self.output == OutputFormat::Json || self.events.is_some()
     }
 }
 
-/// Finds all folders in the current directory and returns a hashmap of tarball names and paths
-fn pathfinder(
-    verbose: bool,
-    current_dir: &Path,
-) -> std::collections::HashMap<String, std::path::PathBuf> {
-    // find current directory
-    if verbose {
-        println!("Working directory: {:?}", current_dir);
-    }
-
-    // start vec of folder paths
-    let mut folder_paths = Vec::new();
-
-    // filter paths to only include folders
-    let paths = std::fs::read_dir(current_dir).unwrap();
-    for path in paths {
-        let path = path.unwrap().path();
-        if verbose {
-            println!("Path: {:?}", path);
-        }
-        if path.is_dir() {
-            if verbose {
-                println!("Folder path detected: {:?}", path);
-            }
-            folder_paths.push(path);
+/// Parses the command line, filling in anything not given on it (or through WRAP_* variables) from wrap.toml
+fn parse_args() -> Args {
+    let argv = std::env::args_os().collect::<Vec<_>>();
+    let command = Args::command();
+    let matches = command.clone().get_matches_from(&argv);
+    let args = Args::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+
+    let target_dir = target_dir_finder(args.target_dir.clone());
+    let Some(path) = Config::find(args.config.as_deref(), &target_dir) else {
+        if let Some(profile) = &args.profile {
+            Args::command()
+                .error(
+                    ErrorKind::ValueValidation,
+                    format!(
+                        "--profile {} given but no {} was found",
+                        profile,
+                        config::FILE_NAME
+                    ),
+                )
+                .exit();
         }
-    }
+        return args;
+    };
+    let config_args = Config::load(&path)
+        .and_then(|config| config.args(args.profile.as_deref(), &command, &matches))
+        .unwrap_or_else(|e| Args::command().error(ErrorKind::ValueValidation, e).exit());
 
-    // start new hashmap for tarball names
-    let mut tarball_names_and_paths = std::collections::HashMap::new();
+    // values from the file go first so anything given on the command line still ends up after them
+    let args = match config_args.is_empty() {
+        true => args,
+        false => Args::parse_from(argv[..1].iter().chain(&config_args).chain(&argv[1..])),
+    };
+    if args.verbose {
+        let _ = writeln!(messages(&args), "Config file: {:?}", path);
+    }
+    args
+}
 
-    // iterate over folder paths and add to hashmap with {folderName}.tar as key and path as value
-    for folder_path in folder_paths {
-        let folder_name = folder_path.file_name().unwrap().to_str().unwrap();
-        if verbose {
-            println!("Folder name: {:?}", folder_name);
-        }
-        let tarball_name = format!("{}.tar", folder_name);
-        if verbose {
-            println!("Tarball name: {:?}", tarball_name);
-        }
-        tarball_names_and_paths.insert(tarball_name, folder_path);
+/// Where human readable output goes - stderr if stdout is reserved for JSON
+fn messages(args: &Args) -> Box<dyn Write> {
+    match args.machine_readable() {
+        true => Box::new(std::io::stderr()),
+        false => Box::new(std::io::stdout()),
     }
+}
 
-    // print hashmap if verbose
-    if verbose {
-        println!("Tarball names and paths: {:?}", tarball_names_and_paths);
+/// Prints the library's messages - Errors always go to stderr, everything else to stdout unless it is reserved
+/// for JSON
+///
+/// Busy folders are only prompted about when stdin is a terminal.
+fn message_sink(machine_readable: bool) -> Messages {
+    let print = move |level: Level, line: &str| {
+        // output that cannot be written (e.g. a closed pipe) is not worth failing a folder over
+        let _ = match (level, machine_readable) {
+            (Level::Info, false) => writeln!(std::io::stdout(), "{}", line),
+            _ => writeln!(std::io::stderr(), "{}", line),
+        };
+    };
+    let messages = Messages::new(print);
+    match std::io::stdin().is_terminal() {
+        true => messages.with_prompt(move |message| {
+            for line in message {
+                print(Level::Info, line);
+            }
+            let mut input = String::new();
+            matches!(std::io::stdin().read_line(&mut input), Ok(read) if read > 0)
+        }),
+        false => messages,
     }
+}
 
-    tarball_names_and_paths
+/// Writes an event to stdout as a single line of JSON
+///
+/// Errors are ignored so that a consumer closing the stream early does not interrupt the run.
+fn emit(event: &Event) {
+    let mut stdout = std::io::stdout().lock();
+    let _ = serde_json::to_writer(&mut stdout, event)
+        .map_err(std::io::Error::from)
+        .and_then(|_| writeln!(stdout))
+        .and_then(|_| stdout.flush());
 }
 
-/// Creates tarballs from the folder paths in the hashmap
-fn tarballer(
-    dry_run: bool,
-    verbose: bool,
-    remove: bool,
-    names_and_paths: std::collections::HashMap<String, std::path::PathBuf>,
-    current_dir: &Path,
-) {
-    // iterate over hashmap and create tarballs
-    for (tarball_name, folder_path) in names_and_paths {
-        let tarball_name = tarball_name.to_string();
-        if verbose {
-            println!("Tarball name: {:?}", tarball_name);
-        }
-        let folder_path = folder_path.to_str().unwrap();
-        if verbose {
-            println!("Folder path: {:?}", folder_path);
-        }
-        let tarball_path = format!("{}/{}", current_dir.to_str().unwrap(), tarball_name);
-        if verbose {
-            println!("Tarball path: {:?}", tarball_path);
+/// Timestamp reproducible archives are clamped to when neither --reproducible=TIMESTAMP nor
+/// SOURCE_DATE_EPOCH is given (1980-01-01, the earliest date a zip archive can store)
+const DEFAULT_SOURCE_DATE_EPOCH: u64 = 315532800;
+
+/// Exit code when nothing could be done, or every folder failed
+const EXIT_FAILURE: u8 = 1;
+/// Exit code when some folders failed and others succeeded (clap uses 2 for usage errors)
+const EXIT_PARTIAL_FAILURE: u8 = 3;
+
+fn main() -> ExitCode {
+    let mut args = parse_args();
+    let target_dir = target_dir_finder(args.target_dir.clone());
+    let keep_going = args.keep_going;
+    let (output, events) = (args.output, args.events);
+    let mut messages = messages(&args);
+    let sink = message_sink(args.machine_readable());
+    if let Some(None) = args.reproducible {
+        match source_date_epoch() {
+            Ok(epoch) => args.reproducible = Some(Some(epoch)),
+            Err(message) => Args::command()
+                .error(ErrorKind::ValueValidation, message)
+                .exit(),
         }
-        let tarball_path = tarball_path.to_string();
-        if verbose {
-            println!("Tarball path as String: {:?}", tarball_path);
+    }
+
+    let report = match args.unwrap {
+        true => {
+            let job = UnwrapJob::with_options(target_dir, Options::from(args)).messages(sink);
+            match events {
+                Some(EventFormat::Ndjson) => job.run_with_events(emit),
+                None => job.run(),
+            }
         }
-        match dry_run {
-            true => {
-                println!("Dry run - would tarball folder: {:?}", folder_path);
-                match remove {
-                    true => {
-                        println!("Dry run - would remove folder: {:?}", folder_path);
-                    }
-                    false => {
-                        println!("Dry run - would NOT remove folder: {:?}", folder_path);
-                    }
-                }
+        false => {
+            let job = WrapJob::with_options(target_dir, Options::from(args)).messages(sink);
+            if let Err(e) = job.validate() {
+                Args::command().error(ErrorKind::ValueValidation, e).exit();
+            }
+            match events {
+                Some(EventFormat::Ndjson) => job.run_with_events(emit),
+                None => job.run(),
             }
+        }
+    };
 
-            false => {
-                if verbose {
-                    println!("Tarballing folder: {:?}", folder_path);
-                }
-                let file = File::create(tarball_path).unwrap();
-                let mut archive = Builder::new(file);
-                archive.append_dir_all(folder_path, folder_path).unwrap();
-                if verbose {
-                    println!("Tarball created: {:?}", tarball_name);
-                }
-                match remove {
-                    true => {
-                        if verbose {
-                            println!("Removing folder: {:?}", folder_path);
-                        }
-                        remove_dir(folder_path, verbose);
-                    }
-                    false => {
-                        if verbose {
-                            println!("Not removing folder: {:?}", folder_path);
-                        }
-                    }
+    match report {
+        Ok(report) => {
+            if output == OutputFormat::Json {
+                match serde_json::to_string_pretty(&report) {
+                    Ok(json) => println!("{}", json),
+                    Err(e) => eprintln!("Error: could not serialize report: {}", e),
                 }
             }
+            if keep_going {
+                let _ = print_summary(&mut messages, &report);
+            }
+            exit_code(&report)
+        }
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            ExitCode::from(EXIT_FAILURE)
         }
     }
 }
 
-fn remove_dir(path: &str, verbose: bool) {
-    loop {
-        if verbose {
-            println!("Attempting to remove folder: {:?}", path);
+/// Reads the timestamp for --reproducible from SOURCE_DATE_EPOCH (see <https://reproducible-builds.org/specs/source-date-epoch/>)
+fn source_date_epoch() -> Result<u64, String> {
+    match std::env::var("SOURCE_DATE_EPOCH") {
+        Ok(epoch) => epoch
+            .trim()
+            .parse()
+            .map_err(|_| format!("SOURCE_DATE_EPOCH is not a valid timestamp: {:?}", epoch)),
+        Err(_) => Ok(DEFAULT_SOURCE_DATE_EPOCH),
+    }
+}
+
+/// Prints a table with the outcome of every folder, followed by the totals
+fn print_summary(out: &mut dyn Write, report: &Report) -> std::io::Result<()> {
+    let rows = report
+        .folders
+        .iter()
+        .map(|folder| {
+            let detail = match &folder.error {
+                Some(e) => e.to_string(),
+                None => folder.archive.display().to_string(),
+            };
+            (folder.status, folder.folder.display().to_string(), detail)
+        })
+        .collect::<Vec<_>>();
+    let width = rows
+        .iter()
+        .map(|(_, folder, _)| folder.len())
+        .max()
+        .unwrap_or(0)
+        .max("FOLDER".len());
+
+    writeln!(out)?;
+    writeln!(
+        out,
+        "{:<8}  {:<width$}  ARCHIVE / ERROR",
+        "STATUS", "FOLDER"
+    )?;
+    for (status, folder, detail) in rows {
+        writeln!(
+            out,
+            "{:<8}  {:<width$}  {}",
+            status.to_string(),
+            folder,
+            detail
+        )?;
+    }
+    writeln!(
+        out,
+        "{} succeeded, {} skipped, {} failed",
+        report.count(Status::Archived) + report.count(Status::DryRun),
+        report.count(Status::Skipped),
+        report.count(Status::Failed)
+    )?;
+    if let Some(e) = &report.manifest_error {
+        writeln!(out, "Checksum manifest not written: {}", e)?;
+    }
+    Ok(())
+}
+
+/// Exit code for a finished run - Failure only if folders failed and none were archived, partial failure if some
+/// folders (or the checksum manifest) failed
+fn exit_code(report: &Report) -> ExitCode {
+    let failed = report.count(Status::Failed);
+    if failed == 0 {
+        // every archive was written but the checksum manifest listing them was not
+        return match report.manifest_error {
+            Some(_) => ExitCode::from(EXIT_PARTIAL_FAILURE),
+            None => ExitCode::SUCCESS,
+        };
+    }
+    match failed == report.folders.len() - report.count(Status::Skipped) {
+        true => ExitCode::from(EXIT_FAILURE),
+        false => ExitCode::from(EXIT_PARTIAL_FAILURE),
+    }
+}
+
+impl From<Args> for Options {
+    fn from(args: Args) -> Self {
+        let mut options = Options::default();
+        options.verbose = args.verbose;
+        options.remove = args.remove;
+        options.dry_run = args.dry_run;
+        options.verify = args.verify;
+        options.checksum = args.checksum;
+        options.depth = args.depth;
+        options.min_depth = args.min_depth;
+        options.include = args.include;
+        options.exclude = args.exclude;
+        options.regex = args.regex;
+        options.exclude_from_archive = args.exclude_from_archive;
+        options.gitignore = !args.no_gitignore;
+        options.exclude_caches = args.exclude_caches;
+        options.exclude_vcs = args.exclude_vcs;
+        options.on_conflict = args.on_conflict;
+        options.no_prompt = args.no_prompt;
+        options.retries = args.retries;
+        options.backoff_ms = args.backoff_ms;
+        options.keep_going = args.keep_going;
+        options.reproducible = args.reproducible.flatten();
+        options.jobs = args.jobs;
+        options.format = args.format;
+        options.compression = Settings {
+            codec: args.compress,
+            level: args.level,
+            long: args.long,
+            threads: args.threads,
+        };
+        options.archive_root = args.archive_root;
+        options.output_dir = args.output_dir;
+        options.name_template = args.name_template;
+        options
+    }
+}
+
+fn target_dir_finder(target_dir: Option<String>) -> PathBuf {
+    // a missing directory is reported when it is scanned
+    match target_dir {
+        Some(dir) => PathBuf::from(dir),
+        None => {
+            // If no target directory is provided, use the current directory
+            PathBuf::from(".")
         }
-        let remover = std::fs::remove_dir_all(path);
-        match remover {
-            Ok(_) => {
-                if verbose {
-                    println!("Removed folder: {:?}", path);
-                }
-                break;
-            }
-            Err(e) => match e.kind() {
-                std::io::ErrorKind::NotFound => {
-                    if verbose {
-                        println!("Folder not found: {:?}", path);
-                    }
-                    break;
-                }
-                std::io::ErrorKind::ResourceBusy => {
-                    println!("Folder is busy: {:?}", path);
-                    println!("Please close any open files in the folder and press Enter to retry.");
-                    let mut input = String::new();
-                    std::io::stdin().read_line(&mut input).unwrap();
-                }
-                std::io::ErrorKind::PermissionDenied => {
-                    println!("Permission denied: {:?}", path);
-                    println!(
-                        "Please check your permissions (you may have a file open inside the directory) and press Enter to retry."
-                    );
-                    let mut input = String::new();
-                    std::io::stdin().read_line(&mut input).unwrap();
-                }
-                _ => {
-                    if verbose {
-                        println!("Error removing folder: {:?}", e);
-                    }
-                    break;
-                }
-            },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn report(statuses: &[Status]) -> Report {
+        let folders = statuses
+            .iter()
+            .map(|status| wrap::FolderReport {
+                folder: PathBuf::from("folder"),
+                name: "folder.tar".to_string(),
+                archive: PathBuf::from("folder.tar"),
+                status: *status,
+                bytes_in: 0,
+                bytes_out: 0,
+                entries: 0,
+                duration: Duration::ZERO,
+                checksum: None,
+                removed: false,
+                error: None,
+            })
+            .collect();
+        Report {
+            folders,
+            ..Report::default()
         }
     }
+
+    #[test]
+    fn exit_codes() {
+        let code = |statuses: &[Status]| exit_code(&report(statuses));
+        assert_eq!(code(&[]), ExitCode::SUCCESS);
+        assert_eq!(
+            code(&[Status::Archived, Status::Skipped]),
+            ExitCode::SUCCESS
+        );
+        assert_eq!(code(&[Status::DryRun]), ExitCode::SUCCESS);
+        assert_eq!(
+            code(&[Status::Failed, Status::Skipped]),
+            ExitCode::from(EXIT_FAILURE)
+        );
+        assert_eq!(
+            code(&[Status::Archived, Status::Failed]),
+            ExitCode::from(EXIT_PARTIAL_FAILURE)
+        );
+    }
+
+    #[test]
+    fn a_missing_manifest_is_a_partial_failure() {
+        let mut report = report(&[Status::Archived]);
+        report.manifest_error = Some(wrap::Error::Options("no space".to_string()));
+        assert_eq!(exit_code(&report), ExitCode::from(EXIT_PARTIAL_FAILURE));
+    }
 }
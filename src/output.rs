@@ -0,0 +1,142 @@
+use std::fmt;
+use std::sync::{Arc, Mutex};
+
+/// Held while flushing or prompting so the output of folders handled in parallel never interleaves
+static LOCK: Mutex<()> = Mutex::new(());
+
+/// How a message should be shown
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Level {
+    /// Progress, dry run listings and verbose output
+    Info,
+    /// Errors and warnings
+    Error,
+}
+
+/// Receives each message with its level
+type Sink = Arc<dyn Fn(Level, &str) + Send + Sync>;
+
+/// Shows a message and waits for the user, returning false if nobody answered
+type Prompt = Arc<dyn Fn(&[String]) -> bool + Send + Sync>;
+
+/// Where the human readable messages of a run go
+///
+/// The library never prints or reads from the terminal itself: messages are handed to the sink (and dropped if
+/// there is none), and a busy folder is only prompted about if a prompt is given.
+#[derive(Clone, Default)]
+pub struct Messages {
+    sink: Option<Sink>,
+    prompt: Option<Prompt>,
+}
+
+impl Messages {
+    /// Sends every message, one line at a time, to `sink`
+    pub fn new(sink: impl Fn(Level, &str) + Send + Sync + 'static) -> Self {
+        Messages {
+            sink: Some(Arc::new(sink)),
+            prompt: None,
+        }
+    }
+
+    /// Shows a message to the user and waits for them to retry - Returns false if nobody is left to answer
+    ///
+    /// Without a prompt, removing a busy folder is retried with backoff instead.
+    pub fn with_prompt(
+        mut self,
+        prompt: impl Fn(&[String]) -> bool + Send + Sync + 'static,
+    ) -> Self {
+        self.prompt = Some(Arc::new(prompt));
+        self
+    }
+
+    /// Whether the user can be prompted
+    pub(crate) fn can_prompt(&self) -> bool {
+        self.prompt.is_some()
+    }
+
+    pub(crate) fn info(&self, line: impl AsRef<str>) {
+        self.send(Level::Info, line.as_ref());
+    }
+
+    pub(crate) fn error(&self, line: impl AsRef<str>) {
+        self.send(Level::Error, line.as_ref());
+    }
+
+    fn send(&self, level: Level, line: &str) {
+        if let Some(sink) = &self.sink {
+            sink(level, line);
+        }
+    }
+}
+
+impl fmt::Debug for Messages {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Messages")
+            .field("sink", &self.sink.is_some())
+            .field("prompt", &self.prompt.is_some())
+            .finish()
+    }
+}
+
+/// Sends a formatted info message - `println!` for a `Messages`
+macro_rules! say {
+    ($messages:expr, $($arg:tt)*) => {
+        $messages.info(format!($($arg)*))
+    };
+}
+pub(crate) use say;
+
+/// Output produced while handling a single folder
+///
+/// When buffered, lines are held back and sent together by `flush` so that the output of folders
+/// tarballed in parallel never interleaves.
+pub struct Output<'a> {
+    messages: &'a Messages,
+    buffered: bool,
+    /// Held back lines with their level
+    lines: Vec<(Level, String)>,
+}
+
+impl<'a> Output<'a> {
+    pub fn new(messages: &'a Messages, buffered: bool) -> Self {
+        Output {
+            messages,
+            buffered,
+            lines: Vec::new(),
+        }
+    }
+
+    /// Sends a line, or holds it back until `flush` if buffered
+    pub fn line(&mut self, line: String) {
+        match self.buffered {
+            true => self.lines.push((Level::Info, line)),
+            false => self.messages.info(line),
+        }
+    }
+
+    /// Sends an error line, or holds it back until `flush` if buffered
+    pub fn error(&mut self, line: String) {
+        match self.buffered {
+            true => self.lines.push((Level::Error, line)),
+            false => self.messages.error(line),
+        }
+    }
+
+    /// Sends every held back line in one go
+    pub fn flush(&mut self) {
+        let _lock = LOCK.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        for (level, line) in self.lines.drain(..) {
+            self.messages.send(level, &line);
+        }
+    }
+
+    /// Prompts the user and waits for them to retry - Returns false if there is no prompt or nobody answered
+    pub fn prompt(&mut self, message: &[String]) -> bool {
+        self.flush();
+        let _lock = LOCK.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        match &self.messages.prompt {
+            Some(prompt) => prompt(message),
+            None => false,
+        }
+    }
+}
@@ -0,0 +1,195 @@
+use crate::error::Error;
+use crate::filter::FolderFilter;
+use crate::output::say;
+use crate::report::FolderReport;
+use crate::walk::{archive_name, Excludes};
+use crate::Options;
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+/// Finds all folders in the current directory (or below it, see --depth) and returns a hashmap of tarball names and paths
+///
+/// Tarball names are relative to the current directory so that each tarball is written next to its folder, and
+/// are made from the name template. Fails if the template gives two folders the same tarball name.
+///
+/// With `keep_going` set, folders that could not be searched or named are returned as failed reports instead of
+/// ending the search.
+pub(crate) fn pathfinder(
+    options: &Options,
+    current_dir: &Path,
+    filter: &FolderFilter,
+    excludes: &Excludes,
+) -> Result<(HashMap<String, PathBuf>, Vec<FolderReport>), Error> {
+    let verbose = options.verbose;
+    // find current directory
+    if verbose {
+        say!(options.messages, "Working directory: {:?}", current_dir);
+    }
+
+    // start vecs of folder paths and folders that could not be searched
+    let mut folder_paths = Vec::new();
+    let mut failed = Vec::new();
+
+    // filter paths to only include folders at the requested depth
+    folder_finder(
+        options,
+        current_dir,
+        1,
+        filter,
+        &mut folder_paths,
+        &mut failed,
+    )?;
+
+    // start new hashmap for tarball names
+    let mut tarball_names_and_paths = HashMap::new();
+
+    // iterate over folder paths and add to hashmap with {parentPath}/{templatedName}.{extension} as key and path as value
+    let extension = options.format.extension(options.compression.codec);
+    let now = chrono::Local::now();
+    for folder_path in folder_paths {
+        let folder_name = match options.name_template.render(&folder_path, excludes, &now) {
+            Ok(folder_name) => folder_name,
+            Err(e) => {
+                fail(options, &folder_path, e, &mut failed)?;
+                continue;
+            }
+        };
+        if verbose {
+            say!(options.messages, "Folder name: {:?}", folder_name);
+        }
+        let parent_path = folder_path
+            .strip_prefix(current_dir)
+            .unwrap()
+            .parent()
+            .unwrap();
+        let tarball_name =
+            archive_name(&parent_path.join(format!("{}.{}", folder_name, extension)));
+        if verbose {
+            say!(options.messages, "Tarball name: {:?}", tarball_name);
+        }
+        match tarball_names_and_paths.entry(tarball_name) {
+            Entry::Occupied(other) => {
+                return Err(Error::Options(format!(
+                    "name template {:?} gives {:?} for both {:?} and {:?}",
+                    options.name_template.to_string(),
+                    other.key(),
+                    other.get(),
+                    folder_path
+                )))
+            }
+            Entry::Vacant(vacant) => {
+                vacant.insert(folder_path);
+            }
+        }
+    }
+
+    // print hashmap if verbose
+    if verbose {
+        say!(
+            options.messages,
+            "Tarball names and paths: {:?}",
+            tarball_names_and_paths
+        );
+    }
+
+    Ok((tarball_names_and_paths, failed))
+}
+
+/// Adds the folders inside `dir` that should be tarballed, descending until `depth` is reached
+///
+/// Folders at `depth` are always selected. Shallower folders are selected if they are at least `min_depth`
+/// deep and contain no subfolders, otherwise they are searched.
+fn folder_finder(
+    options: &Options,
+    dir: &Path,
+    level: usize,
+    filter: &FolderFilter,
+    folder_paths: &mut Vec<PathBuf>,
+    failed: &mut Vec<FolderReport>,
+) -> Result<(), Error> {
+    let (verbose, depth) = (options.verbose, options.depth);
+    let min_depth = options.min_depth.unwrap_or(depth);
+    let scan_error = |source| Error::Scan {
+        path: dir.to_path_buf(),
+        source,
+    };
+    let paths = std::fs::read_dir(dir).map_err(scan_error)?;
+    for path in paths {
+        let path = path.map_err(scan_error)?.path();
+        if verbose {
+            say!(options.messages, "Path: {:?}", path);
+        }
+        if !path.is_dir() {
+            continue;
+        }
+        let folder_name = path.file_name().unwrap().to_string_lossy();
+        if filter.is_excluded(&path) {
+            if verbose {
+                say!(options.messages, "Folder excluded: {:?}", path);
+            }
+            continue;
+        }
+        let has_subfolders = match level < depth {
+            true => match has_subfolders(&path) {
+                Ok(has_subfolders) => has_subfolders,
+                Err(source) => {
+                    let e = Error::Scan {
+                        path: path.clone(),
+                        source,
+                    };
+                    fail(options, &path, e, failed)?;
+                    continue;
+                }
+            },
+            false => false,
+        };
+        if level == depth || (level >= min_depth && !has_subfolders) {
+            if !filter.is_included(&folder_name) {
+                if verbose {
+                    say!(options.messages, "Folder not included: {:?}", path);
+                }
+                continue;
+            }
+            if verbose {
+                say!(options.messages, "Folder path detected: {:?}", path);
+            }
+            folder_paths.push(path);
+        } else if has_subfolders {
+            if verbose {
+                say!(options.messages, "Searching folder: {:?}", path);
+            }
+            if let Err(e) = folder_finder(options, &path, level + 1, filter, folder_paths, failed) {
+                fail(options, &path, e, failed)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Records a folder that could not be searched or named as failed with `keep_going` set, otherwise returns the error
+fn fail(
+    options: &Options,
+    folder_path: &Path,
+    e: Error,
+    failed: &mut Vec<FolderReport>,
+) -> Result<(), Error> {
+    if !options.keep_going {
+        return Err(e);
+    }
+    options.messages.error(format!("Error: {}", e));
+    let mut report = FolderReport::new(folder_path.to_path_buf(), String::new(), PathBuf::new());
+    report.fail(e);
+    failed.push(report);
+    Ok(())
+}
+
+/// Whether a folder contains any folders itself
+fn has_subfolders(path: &Path) -> std::io::Result<bool> {
+    for child in std::fs::read_dir(path)? {
+        if child?.path().is_dir() {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
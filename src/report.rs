@@ -0,0 +1,185 @@
+use crate::error::Error;
+use serde::{Serialize, Serializer};
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// What happened to a folder during a run
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Status {
+    /// The archive was created (and verified, if verification was requested)
+    Archived,
+    /// Nothing was written because the run was a dry run
+    DryRun,
+    /// The folder was deliberately left alone
+    Skipped,
+    /// Something went wrong (see `FolderReport::error`) and the folder was kept
+    Failed,
+}
+
+impl fmt::Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let status = match self {
+            Status::Archived => "ok",
+            Status::DryRun => "dry run",
+            Status::Skipped => "skipped",
+            Status::Failed => "failed",
+        };
+        write!(f, "{}", status)
+    }
+}
+
+/// Outcome of archiving a single folder (or extracting a single archive in unwrap mode)
+#[derive(Debug, Serialize)]
+pub struct FolderReport {
+    /// Folder that was archived
+    #[serde(serialize_with = "serialize_path")]
+    pub folder: PathBuf,
+    /// Name of the archive relative to the target directory, as listed in the checksum manifest
+    pub name: String,
+    /// Location of the archive
+    #[serde(serialize_with = "serialize_path")]
+    pub archive: PathBuf,
+    pub status: Status,
+    /// Bytes read - The size of the files archived, or of the archive in unwrap mode
+    pub bytes_in: u64,
+    /// Bytes written - The size of the archive, or of the files extracted in unwrap mode
+    pub bytes_out: u64,
+    /// Number of entries (files and directories) archived or extracted
+    pub entries: u64,
+    /// Time spent on the folder
+    #[serde(rename = "duration_ms", serialize_with = "serialize_millis")]
+    pub duration: Duration,
+    /// Hex digest of the archive, if a checksum algorithm was chosen
+    pub checksum: Option<String>,
+    /// Whether the folder was removed after archiving (or the archive after extracting)
+    pub removed: bool,
+    /// Why the folder failed, if it did
+    #[serde(serialize_with = "serialize_error")]
+    pub error: Option<Error>,
+}
+
+impl FolderReport {
+    pub(crate) fn new(folder: PathBuf, name: String, archive: PathBuf) -> Self {
+        FolderReport {
+            folder,
+            name,
+            archive,
+            status: Status::DryRun,
+            bytes_in: 0,
+            bytes_out: 0,
+            entries: 0,
+            duration: Duration::ZERO,
+            checksum: None,
+            removed: false,
+            error: None,
+        }
+    }
+
+    /// Marks the folder as failed because of `error`
+    pub(crate) fn fail(&mut self, error: Error) {
+        self.status = Status::Failed;
+        self.error = Some(error);
+    }
+}
+
+/// Outcome of a whole run, with one report per folder sorted by folder path
+#[derive(Debug, Default, Serialize)]
+pub struct Report {
+    pub folders: Vec<FolderReport>,
+    /// Checksum manifest written by the run, if any
+    #[serde(serialize_with = "serialize_optional_path")]
+    pub manifest: Option<PathBuf>,
+    /// Why the checksum manifest could not be written, if it could not
+    #[serde(serialize_with = "serialize_error")]
+    pub manifest_error: Option<Error>,
+}
+
+impl Report {
+    /// Number of folders with the given status
+    pub fn count(&self, status: Status) -> usize {
+        self.folders
+            .iter()
+            .filter(|folder| folder.status == status)
+            .count()
+    }
+
+    /// Folders that failed, with the error for each
+    pub fn failures(&self) -> impl Iterator<Item = (&FolderReport, &Error)> {
+        self.folders
+            .iter()
+            .filter_map(|folder| Some((folder, folder.error.as_ref()?)))
+    }
+}
+
+/// Serializes a path as a string, replacing anything that is not valid UTF-8
+pub(crate) fn serialize_path<S: Serializer>(path: &Path, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&path.to_string_lossy())
+}
+
+fn serialize_optional_path<S: Serializer>(
+    path: &Option<PathBuf>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    match path {
+        Some(path) => serialize_path(path, serializer),
+        None => serializer.serialize_none(),
+    }
+}
+
+fn serialize_millis<S: Serializer>(duration: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_u128(duration.as_millis())
+}
+
+/// Serializes an error as its message
+fn serialize_error<S: Serializer>(error: &Option<Error>, serializer: S) -> Result<S::Ok, S::Error> {
+    match error {
+        Some(error) => serializer.serialize_str(&error.to_string()),
+        None => serializer.serialize_none(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn serializes_folders_with_stable_keys() {
+        let mut folder = FolderReport::new("a".into(), "a.tar".to_string(), "out/a.tar".into());
+        folder.duration = Duration::from_micros(1500);
+        folder.fail(Error::Conflict {
+            archive: "out/a.tar".into(),
+        });
+        let report = Report {
+            folders: vec![folder],
+            ..Report::default()
+        };
+        let json = serde_json::to_value(&report).unwrap();
+        assert_eq!(json["manifest"], serde_json::Value::Null);
+        assert_eq!(json["manifest_error"], serde_json::Value::Null);
+        let folder = &json["folders"][0];
+        let keys = folder.as_object().unwrap().keys().collect::<Vec<_>>();
+        assert_eq!(
+            keys,
+            [
+                "archive",
+                "bytes_in",
+                "bytes_out",
+                "checksum",
+                "duration_ms",
+                "entries",
+                "error",
+                "folder",
+                "name",
+                "removed",
+                "status"
+            ]
+        );
+        assert_eq!(folder["folder"], "a");
+        assert_eq!(folder["archive"], "out/a.tar");
+        assert_eq!(folder["status"], "failed");
+        assert_eq!(folder["duration_ms"], 1);
+        assert!(folder["error"].as_str().unwrap().contains("out/a.tar"));
+    }
+}
@@ -0,0 +1,70 @@
+use std::fmt;
+use std::path::{Component, Path, PathBuf};
+use std::str::FromStr;
+
+/// Where the entries for a folder are stored inside its archive
+#[derive(Clone, Debug, PartialEq, Eq, Default)]
+pub enum ArchiveRoot {
+    /// Under a directory named after the folder (`foo/...`) - When unwrapping, the one directory everything in
+    /// the archive is under is stripped whatever its name, as the archive may have been renamed since
+    #[default]
+    Folder,
+    /// At the top level of the archive, with no directory around them (`...`)
+    None,
+    /// Under an arbitrary relative path (`prefix/...`)
+    Custom(PathBuf),
+}
+
+impl ArchiveRoot {
+    /// Directory the entries for `folder_path` are stored under - Empty if they are stored at the top level
+    pub fn path(&self, folder_path: &Path) -> PathBuf {
+        match self {
+            ArchiveRoot::Folder => folder_path
+                .file_name()
+                .map(PathBuf::from)
+                .unwrap_or_default(),
+            ArchiveRoot::None => PathBuf::new(),
+            ArchiveRoot::Custom(prefix) => prefix.clone(),
+        }
+    }
+}
+
+impl FromStr for ArchiveRoot {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "folder" => return Ok(ArchiveRoot::Folder),
+            "none" => return Ok(ArchiveRoot::None),
+            _ => {}
+        }
+        let Some(prefix) = s.strip_prefix("custom:") else {
+            return Err(format!(
+                "expected folder, none or custom:<prefix> (got {:?})",
+                s
+            ));
+        };
+        let prefix = Path::new(prefix);
+        if prefix
+            .components()
+            .any(|component| !matches!(component, Component::Normal(_) | Component::CurDir))
+        {
+            return Err(format!(
+                "archive root prefix must be a relative path without '..' (got {:?})",
+                prefix
+            ));
+        }
+        // drop any `.` components so the prefix matches the names read back from the archive
+        Ok(ArchiveRoot::Custom(prefix.components().collect()))
+    }
+}
+
+impl fmt::Display for ArchiveRoot {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ArchiveRoot::Folder => write!(f, "folder"),
+            ArchiveRoot::None => write!(f, "none"),
+            ArchiveRoot::Custom(prefix) => write!(f, "custom:{}", prefix.display()),
+        }
+    }
+}
@@ -0,0 +1,66 @@
+use crate::compression::Compression;
+use crate::error::Error;
+use crate::output::say;
+use crate::walk::{walk, Excludes};
+use crate::Options;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+/// Checks there is room in `output_dir` for the archives of every folder before any are written
+///
+/// The space needed is estimated as the total size of the files that would be archived. Uncompressed archives
+/// are at least that big, so the run fails if they do not fit - compressed archives usually are not, so only a
+/// warning is printed.
+pub(crate) fn check_space(
+    options: &Options,
+    excludes: &Excludes,
+    names_and_paths: &HashMap<String, PathBuf>,
+    output_dir: &Path,
+) -> Result<(), Error> {
+    let available = match fs4::available_space(output_dir) {
+        Ok(available) => available,
+        Err(e) => {
+            options.messages.error(format!(
+                "Warning: could not check free space in {:?}: {}",
+                output_dir, e
+            ));
+            return Ok(());
+        }
+    };
+
+    // folders that cannot be read are left for the run itself to report
+    let needed = names_and_paths
+        .values()
+        .filter_map(|folder_path| walk(folder_path, excludes).ok())
+        .flatten()
+        .filter(|entry| !entry.metadata.is_dir())
+        .map(|entry| entry.metadata.len())
+        .sum::<u64>();
+    if options.verbose {
+        say!(
+            options.messages,
+            "Space needed: {} bytes, available in {:?}: {} bytes",
+            needed,
+            output_dir,
+            available
+        );
+    }
+    if needed <= available {
+        return Ok(());
+    }
+
+    match options.compression.codec {
+        Compression::None => Err(Error::Space {
+            path: output_dir.to_path_buf(),
+            needed,
+            available,
+        }),
+        _ => {
+            options.messages.error(format!(
+                "Warning: {:?} has {} bytes free but the folders hold {} bytes - the compressed archives may not fit",
+                output_dir, available, needed
+            ));
+            Ok(())
+        }
+    }
+}
@@ -0,0 +1,216 @@
+use crate::compression::Settings;
+use crate::walk::{clamped_mtime, mode, normalized_mode, walk, with_path, Excludes, Kind, Totals};
+use std::fs::{File, Metadata};
+use std::io::Write;
+use std::path::Path;
+use tar::{Builder, Header, HeaderMode};
+
+/// Creates a (possibly compressed) tarball containing the folder and everything below it that is not excluded
+///
+/// Entries are stored under `root`, or at the top level of the tarball if it is empty.
+///
+/// With `reproducible` set to a timestamp, mtimes are clamped to it and ownership and permissions are
+/// normalised so identical folders always produce identical tarballs.
+///
+/// Returns the writer along with the number of entries and file bytes that went into the tarball.
+pub fn tar_folder<W: Write>(
+    folder_path: &Path,
+    root: &Path,
+    writer: W,
+    compression: &Settings,
+    excludes: &Excludes,
+    reproducible: Option<u64>,
+) -> std::io::Result<(W, Totals)> {
+    let mut archive = Builder::new(compression.encoder(writer)?);
+    let mut totals = Totals::default();
+    if !root.as_os_str().is_empty() {
+        match reproducible {
+            Some(epoch) => {
+                let metadata = std::fs::metadata(folder_path)?;
+                archive.append_data(
+                    &mut reproducible_header(&metadata, epoch)?,
+                    root,
+                    std::io::empty(),
+                )?;
+            }
+            None => archive.append_dir(root, folder_path)?,
+        }
+        totals.add(0);
+    }
+    for entry in walk(folder_path, excludes)? {
+        let name = root.join(&entry.relative);
+        let kind = entry.kind();
+        match kind {
+            Kind::File => totals.add(entry.metadata.len()),
+            Kind::Dir | Kind::Special => totals.add(0),
+            Kind::Unsupported => {
+                totals.skip(&entry.path);
+                continue;
+            }
+        }
+        match (reproducible, kind) {
+            (Some(epoch), Kind::File) => archive.append_data(
+                &mut reproducible_header(&entry.metadata, epoch)?,
+                name,
+                File::open(&entry.path).map_err(with_path(&entry.path))?,
+            )?,
+            (Some(epoch), _) => archive.append_data(
+                &mut reproducible_header(&entry.metadata, epoch)?,
+                name,
+                std::io::empty(),
+            )?,
+            (None, Kind::Dir) => archive.append_dir(name, &entry.path)?,
+            // tar-rs would store special files under their path on disk rather than `name`
+            (None, Kind::Special) => {
+                let mut header = Header::new_gnu();
+                header.set_metadata_in_mode(&entry.metadata, HeaderMode::Complete);
+                set_special(&mut header, &entry.metadata)?;
+                archive.append_data(&mut header, name, std::io::empty())?
+            }
+            (None, _) => archive.append_path_with_name(&entry.path, name)?,
+        }
+    }
+    Ok((archive.into_inner()?.finish()?, totals))
+}
+
+/// Gives the header of a FIFO or device node no contents and its device numbers
+fn set_special(header: &mut Header, metadata: &Metadata) -> std::io::Result<()> {
+    header.set_size(0);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        // the same split of the device number into major and minor as glibc's
+        let device = metadata.rdev();
+        header
+            .set_device_major((((device >> 32) & 0xffff_f000) | ((device >> 8) & 0xfff)) as u32)?;
+        header.set_device_minor((((device >> 12) & 0xffff_ff00) | (device & 0xff)) as u32)?;
+    }
+    Ok(())
+}
+
+/// Header with no owner, normalised permissions and the mtime clamped to `epoch`
+fn reproducible_header(metadata: &Metadata, epoch: u64) -> std::io::Result<Header> {
+    let mut header = Header::new_gnu();
+    header.set_metadata_in_mode(metadata, HeaderMode::Deterministic);
+    header.set_mode(normalized_mode(metadata.is_dir(), mode(metadata)));
+    header.set_mtime(clamped_mtime(metadata, epoch));
+    if Kind::of(metadata) == Kind::Special {
+        set_special(&mut header, metadata)?;
+    }
+    Ok(header)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn excludes(patterns: &[&str]) -> Excludes {
+        let patterns = patterns.iter().map(|p| p.to_string()).collect::<Vec<_>>();
+        Excludes::new(&patterns, false, false, false).unwrap()
+    }
+
+    /// Names and entry types in a tarball
+    fn list(tarball: &[u8]) -> Vec<(String, tar::EntryType)> {
+        tar::Archive::new(tarball)
+            .entries()
+            .unwrap()
+            .map(|entry| {
+                let entry = entry.unwrap();
+                let name = entry.path().unwrap().to_string_lossy().into_owned();
+                (name, entry.header().entry_type())
+            })
+            .collect()
+    }
+
+    fn folder(dir: &Path) -> PathBuf {
+        let folder = dir.join("foo");
+        std::fs::create_dir_all(folder.join("target")).unwrap();
+        std::fs::write(folder.join("a.rs"), "fn main() {}").unwrap();
+        std::fs::write(folder.join("a.o"), "").unwrap();
+        std::fs::write(folder.join("target/out"), "").unwrap();
+        folder
+    }
+
+    #[test]
+    fn leaves_out_excluded_entries() {
+        let dir = tempfile::tempdir().unwrap();
+        let folder = folder(dir.path());
+        let settings = Settings::default();
+        let excludes = excludes(&["*.o", "target/"]);
+        let (tarball, totals) = tar_folder(
+            &folder,
+            Path::new("foo"),
+            Vec::new(),
+            &settings,
+            &excludes,
+            None,
+        )
+        .unwrap();
+        assert_eq!(
+            list(&tarball),
+            [
+                ("foo".to_string(), tar::EntryType::Directory),
+                ("foo/a.rs".to_string(), tar::EntryType::Regular),
+            ]
+        );
+        assert_eq!((totals.entries, totals.bytes), (2, 12));
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn stores_fifos_under_the_root() {
+        let dir = tempfile::tempdir().unwrap();
+        let folder = folder(dir.path());
+        let status = std::process::Command::new("mkfifo")
+            .arg(folder.join("pipe"))
+            .status()
+            .unwrap();
+        assert!(status.success());
+        let settings = Settings::default();
+        let excludes = excludes(&["*.o", "target/"]);
+        let (tarball, _) = tar_folder(
+            &folder,
+            Path::new(""),
+            Vec::new(),
+            &settings,
+            &excludes,
+            None,
+        )
+        .unwrap();
+        assert_eq!(
+            list(&tarball),
+            [
+                ("a.rs".to_string(), tar::EntryType::Regular),
+                ("pipe".to_string(), tar::EntryType::Fifo),
+            ]
+        );
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn reproducible_tarballs_are_identical() {
+        use std::os::unix::fs::PermissionsExt;
+        let tarball = |dir: &Path, mode: u32| {
+            let folder = folder(dir);
+            std::fs::set_permissions(folder.join("a.rs"), std::fs::Permissions::from_mode(mode))
+                .unwrap();
+            let settings = Settings::default();
+            tar_folder(
+                &folder,
+                Path::new("foo"),
+                Vec::new(),
+                &settings,
+                &excludes(&[]),
+                Some(315532800),
+            )
+            .unwrap()
+            .0
+        };
+        let (first, second) = (tempfile::tempdir().unwrap(), tempfile::tempdir().unwrap());
+        // made at different times with different permissions
+        let first = tarball(first.path(), 0o600);
+        std::thread::sleep(std::time::Duration::from_millis(1100));
+        assert_eq!(first, tarball(second.path(), 0o640));
+    }
+}
@@ -0,0 +1,704 @@
+use crate::checksum::{self, HashingWriter};
+use crate::conflict::{self, OnConflict};
+use crate::error::Error;
+use crate::events::Event;
+use crate::format::Format;
+use crate::output::{say, Output};
+use crate::report::{FolderReport, Report, Status};
+use crate::walk::{Excludes, Totals};
+use crate::{tarball, verify, zipper, Options};
+use std::fs::File;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::Instant;
+
+/// Creates tarballs from the folder paths in the hashmap
+///
+/// Unless `keep_going` is set, stops handing out folders once one has failed - Folders that were never
+/// started are left out of the report. A checksum manifest that could not be written is recorded in the report.
+pub(crate) fn tarballer(
+    options: &Options,
+    excludes: &Excludes,
+    names_and_paths: std::collections::HashMap<String, std::path::PathBuf>,
+    archive_dir: &Path,
+    on_event: &(dyn Fn(&Event) + Sync),
+) -> Report {
+    let jobs = match options.jobs {
+        0 => std::thread::available_parallelism().map_or(1, |n| n.get()),
+        jobs => jobs,
+    };
+    if options.verbose {
+        say!(options.messages, "Jobs: {}", jobs);
+    }
+
+    // start vec of reports, one per folder
+    let folders = Mutex::new(Vec::new());
+
+    // share the hashmap between workers, each taking the next folder until none are left
+    let queue = Mutex::new(names_and_paths.into_iter());
+    let failed = AtomicBool::new(false);
+    std::thread::scope(|scope| {
+        for _ in 0..jobs {
+            scope.spawn(|| loop {
+                if failed.load(Ordering::Relaxed) {
+                    break;
+                }
+                let next = queue.lock().unwrap().next();
+                let Some((tarball_name, folder_path)) = next else {
+                    break;
+                };
+                // buffer output when running in parallel so folders do not interleave
+                let mut out = Output::new(&options.messages, jobs > 1);
+                on_event(&Event::FolderStart {
+                    folder: &folder_path,
+                    name: &tarball_name,
+                });
+                let started = Instant::now();
+                let mut report = tarball_folder(
+                    options,
+                    excludes,
+                    tarball_name,
+                    &folder_path,
+                    archive_dir,
+                    &mut out,
+                );
+                report.duration = started.elapsed();
+                out.flush();
+                on_event(&Event::FolderDone(&report));
+                if report.status == Status::Failed && !options.keep_going {
+                    failed.store(true, Ordering::Relaxed);
+                }
+                folders.lock().unwrap().push(report);
+            });
+        }
+    });
+    let mut folders = folders.into_inner().unwrap();
+    folders.sort_by(|a, b| a.folder.cmp(&b.folder));
+
+    // write the checksum manifest
+    let mut report = Report {
+        folders,
+        ..Report::default()
+    };
+    if let Some(algorithm) = options.checksum {
+        let manifest_path = archive_dir.join(algorithm.manifest_name());
+        match options.dry_run {
+            true => {
+                say!(
+                    options.messages,
+                    "Dry run - would write checksum manifest: {:?}",
+                    manifest_path
+                );
+            }
+            false => {
+                let checksums = report
+                    .folders
+                    .iter()
+                    .filter_map(|folder| Some((folder.name.clone(), folder.checksum.clone()?)))
+                    .collect::<Vec<_>>();
+                match checksum::write_manifest(archive_dir, algorithm, &checksums) {
+                    Ok(()) => {
+                        if options.verbose {
+                            say!(
+                                options.messages,
+                                "Checksum manifest written: {:?}",
+                                manifest_path
+                            );
+                        }
+                        report.manifest = Some(manifest_path);
+                    }
+                    // the archives are already written (and folders maybe removed), so the report must survive
+                    Err(source) => {
+                        let e = Error::Manifest {
+                            path: manifest_path,
+                            source,
+                        };
+                        options.messages.error(format!("Error: {}", e));
+                        report.manifest_error = Some(e);
+                    }
+                }
+            }
+        }
+    }
+
+    report
+}
+
+/// Creates the tarball for a single folder
+fn tarball_folder(
+    options: &Options,
+    excludes: &Excludes,
+    mut tarball_name: String,
+    folder_path: &Path,
+    archive_dir: &Path,
+    out: &mut Output<'_>,
+) -> FolderReport {
+    let (dry_run, verbose, remove) = (options.dry_run, options.verbose, options.remove);
+    // folders are always verified before they are removed
+    let verify = options.verify || remove;
+
+    if verbose {
+        out.line(format!("Tarball name: {:?}", tarball_name));
+    }
+    if verbose {
+        out.line(format!("Folder path: {:?}", folder_path));
+    }
+    let mut tarball_path = archive_dir.join(&tarball_name);
+    if verbose {
+        out.line(format!("Tarball path: {:?}", tarball_path));
+    }
+
+    // decide what to do about a tarball left by an earlier run
+    let prefix = match dry_run {
+        true => "Dry run - ",
+        false => "",
+    };
+    if tarball_path.exists() {
+        match options.on_conflict {
+            OnConflict::Overwrite => {
+                if dry_run || verbose {
+                    out.line(format!(
+                        "{}Tarball already exists, overwriting: {:?}",
+                        prefix, tarball_path
+                    ));
+                }
+            }
+            OnConflict::Skip => {
+                out.line(format!(
+                    "{}Tarball already exists, skipping folder: {:?}",
+                    prefix, folder_path
+                ));
+                let mut report =
+                    FolderReport::new(folder_path.to_path_buf(), tarball_name, tarball_path);
+                report.status = Status::Skipped;
+                return report;
+            }
+            OnConflict::Rename => {
+                let extension = options.format.extension(options.compression.codec);
+                let renamed = conflict::renamed(archive_dir, &tarball_name, extension);
+                if dry_run || verbose {
+                    out.line(format!(
+                        "{}Tarball already exists, renaming to: {:?}",
+                        prefix, renamed
+                    ));
+                }
+                tarball_name = renamed;
+                tarball_path = archive_dir.join(&tarball_name);
+            }
+            OnConflict::Fail => {
+                let e = Error::Conflict {
+                    archive: tarball_path.clone(),
+                };
+                out.error(format!("{}Error: {}", prefix, e));
+                let mut report =
+                    FolderReport::new(folder_path.to_path_buf(), tarball_name, tarball_path);
+                report.fail(e);
+                return report;
+            }
+        }
+    }
+
+    let mut report = FolderReport::new(
+        folder_path.to_path_buf(),
+        tarball_name.clone(),
+        tarball_path.clone(),
+    );
+    match dry_run {
+        true => {
+            out.line(format!("Dry run - would tarball folder: {:?}", folder_path));
+            if verify {
+                out.line(format!(
+                    "Dry run - would verify tarball: {:?}",
+                    tarball_path
+                ));
+            }
+            match remove {
+                true => {
+                    out.line(format!("Dry run - would remove folder: {:?}", folder_path));
+                }
+                false => {
+                    out.line(format!(
+                        "Dry run - would NOT remove folder: {:?}",
+                        folder_path
+                    ));
+                }
+            }
+            report.status = Status::DryRun;
+        }
+
+        false => {
+            if verbose {
+                out.line(format!("Tarballing folder: {:?}", folder_path));
+            }
+            let created =
+                create_tarball(options, excludes, folder_path, &tarball_path).map_err(|source| {
+                    Error::Archive {
+                        folder: folder_path.to_path_buf(),
+                        archive: tarball_path.clone(),
+                        source,
+                    }
+                });
+            let Written {
+                digest,
+                totals,
+                size,
+            } = match created {
+                Ok(written) => written,
+                Err(e) => {
+                    out.error(format!("Error: {}", e));
+                    report.fail(e);
+                    return report;
+                }
+            };
+            report.bytes_in = totals.bytes;
+            report.bytes_out = size;
+            report.entries = totals.entries;
+            for path in &totals.skipped {
+                out.error(format!(
+                    "Warning: {:?} cannot be stored in the archive and was left out",
+                    path
+                ));
+            }
+            if verbose {
+                out.line(format!("Tarball created: {:?}", tarball_name));
+            }
+            if let Some(digest) = &digest {
+                if verbose {
+                    out.line(format!("Tarball checksum: {}", digest));
+                }
+            }
+            report.checksum = digest;
+            report.status = Status::Archived;
+            if verify {
+                let mismatches = verify_tarball(options, excludes, folder_path, &tarball_path, out);
+                if !mismatches.is_empty() {
+                    if remove {
+                        out.line(format!(
+                            "Verification failed - NOT removing folder: {:?}",
+                            folder_path
+                        ));
+                    }
+                    report.fail(Error::Verify {
+                        folder: folder_path.to_path_buf(),
+                        archive: tarball_path,
+                        mismatches,
+                    });
+                    return report;
+                }
+            }
+            match remove {
+                true => {
+                    if verbose {
+                        out.line(format!("Removing folder: {:?}", folder_path));
+                    }
+                    match remove_dir(folder_path, options, out) {
+                        Ok(removed) => report.removed = removed,
+                        Err(source) => {
+                            let e = Error::Remove {
+                                path: folder_path.to_path_buf(),
+                                source,
+                            };
+                            out.error(format!("Error: {}", e));
+                            report.fail(e);
+                        }
+                    }
+                }
+                false => {
+                    if verbose {
+                        out.line(format!("Not removing folder: {:?}", folder_path));
+                    }
+                }
+            }
+        }
+    }
+    report
+}
+
+/// What went into a tarball that was written
+struct Written {
+    /// Checksum of the tarball, if one was requested
+    digest: Option<String>,
+    totals: Totals,
+    /// Size of the tarball on disk
+    size: u64,
+}
+
+/// Writes the tarball for a folder
+///
+/// The tarball is written to a hidden `.partial` file next to it and only renamed into place once it is
+/// complete and synced to disk, so an interrupted run never leaves a truncated tarball behind.
+fn create_tarball(
+    options: &Options,
+    excludes: &Excludes,
+    folder_path: &Path,
+    tarball_path: &Path,
+) -> std::io::Result<Written> {
+    // folders below the target directory are mirrored in the output directory
+    if let Some(parent) = tarball_path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let partial_path = partial_path(tarball_path);
+    let written = write_tarball(options, excludes, folder_path, &partial_path)
+        .and_then(|written| std::fs::rename(&partial_path, tarball_path).map(|_| written));
+    if written.is_err() {
+        // the partial file may not exist if creating it was what failed
+        let _ = std::fs::remove_file(&partial_path);
+    }
+    written
+}
+
+/// Writes and syncs the tarball for a folder to `path`
+fn write_tarball(
+    options: &Options,
+    excludes: &Excludes,
+    folder_path: &Path,
+    path: &Path,
+) -> std::io::Result<Written> {
+    let (compression, reproducible) = (&options.compression, options.reproducible);
+    let root = options.archive_root.path(folder_path);
+    let file = File::create(path)?;
+    // hash the tarball as it is written so it never has to be read back
+    let writer = HashingWriter::new(file, options.checksum.map(|a| a.hasher()));
+    let (writer, totals) = match options.format {
+        Format::Tar => tarball::tar_folder(
+            folder_path,
+            &root,
+            writer,
+            compression,
+            excludes,
+            reproducible,
+        )?,
+        Format::Zip => zipper::zip_folder(
+            folder_path,
+            &root,
+            writer,
+            compression,
+            excludes,
+            reproducible,
+        )?,
+    };
+    let (file, digest) = writer.finish();
+    file.sync_all()?;
+    Ok(Written {
+        digest,
+        totals,
+        size: file.metadata()?.len(),
+    })
+}
+
+/// Temporary location a tarball (or the checksum manifest) is written to before being renamed to `tarball_path`
+pub(crate) fn partial_path(tarball_path: &Path) -> std::path::PathBuf {
+    let mut file_name = std::ffi::OsString::from(".");
+    file_name.push(tarball_path.file_name().unwrap_or_default());
+    file_name.push(".partial");
+    tarball_path.with_file_name(file_name)
+}
+
+/// Compares a tarball with its folder, printing and returning every mismatch found
+fn verify_tarball(
+    options: &Options,
+    excludes: &Excludes,
+    folder_path: &Path,
+    tarball_path: &Path,
+    out: &mut Output<'_>,
+) -> Vec<String> {
+    let verbose = options.verbose;
+    if verbose {
+        out.line(format!("Verifying tarball: {:?}", tarball_path));
+    }
+    match verify::verify(
+        folder_path,
+        tarball_path,
+        &options.archive_root.path(folder_path),
+        options.format,
+        options.compression.codec,
+        excludes,
+        options.reproducible.is_some(),
+    ) {
+        Ok(mismatches) if mismatches.is_empty() => {
+            if verbose {
+                out.line(format!("Tarball verified: {:?}", tarball_path));
+            }
+            mismatches
+        }
+        Ok(mismatches) => {
+            out.error(format!("Tarball does not match folder: {:?}", tarball_path));
+            for mismatch in &mismatches {
+                out.error(format!("  {}", mismatch));
+            }
+            mismatches
+        }
+        Err(e) => {
+            out.error(format!("Could not read tarball {:?}: {}", tarball_path, e));
+            vec![format!("could not read tarball: {}", e)]
+        }
+    }
+}
+
+/// Removes a folder, prompting the user to retry while it is busy - Returns false if it was already gone
+///
+/// Without a prompt (or with `no_prompt`) removal is retried `retries` times with exponential
+/// backoff instead, after which the error is returned and the folder is left in place. The error is also returned
+/// if nobody answers the prompt.
+fn remove_dir(path: &Path, options: &Options, out: &mut Output<'_>) -> std::io::Result<bool> {
+    let verbose = options.verbose;
+    let interactive = !options.no_prompt && options.messages.can_prompt();
+    let mut attempt = 0;
+    loop {
+        if verbose {
+            out.line(format!("Attempting to remove folder: {:?}", path));
+        }
+        let remover = std::fs::remove_dir_all(path);
+        match remover {
+            Ok(_) => {
+                if verbose {
+                    out.line(format!("Removed folder: {:?}", path));
+                }
+                return Ok(true);
+            }
+            Err(e) => match e.kind() {
+                std::io::ErrorKind::NotFound => {
+                    if verbose {
+                        out.line(format!("Folder not found: {:?}", path));
+                    }
+                    return Ok(false);
+                }
+                std::io::ErrorKind::ResourceBusy | std::io::ErrorKind::PermissionDenied
+                    if !interactive =>
+                {
+                    if attempt >= options.retries {
+                        return Err(e);
+                    }
+                    let delay = options
+                        .backoff_ms
+                        .saturating_mul(2u64.saturating_pow(attempt));
+                    attempt += 1;
+                    out.error(format!(
+                        "Could not remove folder {:?}: {} - retrying in {} ms ({}/{})",
+                        path, e, delay, attempt, options.retries
+                    ));
+                    std::thread::sleep(std::time::Duration::from_millis(delay));
+                }
+                // with nobody left to answer the prompt, give up on the folder
+                std::io::ErrorKind::ResourceBusy => {
+                    if !out.prompt(&[
+                        format!("Folder is busy: {:?}", path),
+                        "Please close any open files in the folder and press Enter to retry."
+                            .to_string(),
+                    ]) {
+                        return Err(e);
+                    }
+                }
+                std::io::ErrorKind::PermissionDenied => {
+                    if !out.prompt(&[
+                        format!("Permission denied: {:?}", path),
+                        "Please check your permissions (you may have a file open inside the directory) and press Enter to retry.".to_string(),
+                    ]) {
+                        return Err(e);
+                    }
+                }
+                _ => {
+                    if verbose {
+                        out.line(format!("Error removing folder: {:?}", e));
+                    }
+                    return Err(e);
+                }
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::WrapJob;
+
+    /// A target directory holding a folder with one file for every name
+    fn target(names: &[&str]) -> tempfile::TempDir {
+        let dir = tempfile::tempdir().unwrap();
+        for name in names {
+            std::fs::create_dir(dir.path().join(name)).unwrap();
+            std::fs::write(dir.path().join(name).join("file"), name).unwrap();
+        }
+        dir
+    }
+
+    /// Folder names and statuses in a report
+    fn statuses(report: &Report) -> Vec<(String, Status)> {
+        report
+            .folders
+            .iter()
+            .map(|folder| {
+                let name = folder.folder.file_name().unwrap().to_string_lossy();
+                (name.into_owned(), folder.status)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn archives_folders_in_parallel() {
+        let names = ["a", "b", "c", "d", "e", "f", "g", "h"];
+        for jobs in [0, 4] {
+            let dir = target(&names);
+            let report = WrapJob::new(dir.path())
+                .jobs(jobs)
+                .checksum(Some(crate::checksum::Algorithm::Sha256))
+                .run()
+                .unwrap();
+            // reported in folder order whichever job finished first
+            assert_eq!(
+                statuses(&report),
+                names.map(|name| (name.to_string(), Status::Archived))
+            );
+            let manifest = std::fs::read_to_string(dir.path().join("SHA256SUMS")).unwrap();
+            assert_eq!(manifest.lines().count(), names.len());
+            for name in names {
+                assert!(dir.path().join(format!("{}.tar", name)).is_file());
+            }
+        }
+    }
+
+    #[test]
+    fn stops_at_the_first_failure_unless_keeping_going() {
+        let dir = target(&["a", "b", "c"]);
+        for name in ["a", "b", "c"] {
+            std::fs::write(dir.path().join(format!("{}.tar", name)), "").unwrap();
+        }
+        let job = WrapJob::new(dir.path()).on_conflict(OnConflict::Fail);
+        let report = job.run().unwrap();
+        assert_eq!(report.folders.len(), 1);
+        assert_eq!(report.count(Status::Failed), 1);
+
+        let report = job.keep_going(true).run().unwrap();
+        assert_eq!(report.count(Status::Failed), 3);
+    }
+
+    #[test]
+    fn keeps_going_past_failed_folders() {
+        let dir = target(&["a", "b", "c"]);
+        std::fs::write(dir.path().join("b.tar"), "").unwrap();
+        let report = WrapJob::new(dir.path())
+            .on_conflict(OnConflict::Fail)
+            .keep_going(true)
+            .run()
+            .unwrap();
+        assert_eq!(
+            statuses(&report),
+            [
+                ("a".to_string(), Status::Archived),
+                ("b".to_string(), Status::Failed),
+                ("c".to_string(), Status::Archived),
+            ]
+        );
+        assert!(matches!(
+            report.folders[1].error,
+            Some(Error::Conflict { .. })
+        ));
+    }
+
+    #[test]
+    fn handles_existing_tarballs_as_asked() {
+        let run = |on_conflict| {
+            let dir = target(&["a"]);
+            std::fs::write(dir.path().join("a.tar"), "old").unwrap();
+            let report = WrapJob::new(dir.path())
+                .on_conflict(on_conflict)
+                .remove(true)
+                .run()
+                .unwrap();
+            let old = std::fs::read(dir.path().join("a.tar")).unwrap() == b"old";
+            (dir, report, old)
+        };
+
+        let (dir, report, old) = run(OnConflict::Overwrite);
+        assert_eq!(report.count(Status::Archived), 1);
+        assert!(!old);
+        assert!(!dir.path().join("a").exists());
+
+        let (dir, report, old) = run(OnConflict::Skip);
+        assert_eq!(report.count(Status::Skipped), 1);
+        assert!(old);
+        assert!(dir.path().join("a/file").is_file());
+
+        let (dir, report, old) = run(OnConflict::Rename);
+        assert_eq!(report.count(Status::Archived), 1);
+        assert_eq!(report.folders[0].archive, dir.path().join("a-1.tar"));
+        assert!(old);
+        assert!(!dir.path().join("a").exists());
+
+        let (dir, report, old) = run(OnConflict::Fail);
+        assert_eq!(report.count(Status::Failed), 1);
+        assert!(old);
+        assert!(dir.path().join("a/file").is_file());
+    }
+
+    #[test]
+    fn writes_next_to_the_tarball() {
+        assert_eq!(
+            partial_path(Path::new("out/foo.tar.gz")),
+            Path::new("out/.foo.tar.gz.partial")
+        );
+    }
+
+    #[test]
+    fn cleans_up_after_a_failed_write() {
+        let dir = target(&["a"]);
+        // a tarball cannot be renamed over a folder that is not empty
+        std::fs::create_dir(dir.path().join("a.tar")).unwrap();
+        std::fs::write(dir.path().join("a.tar/file"), "").unwrap();
+        let report = WrapJob::new(dir.path()).remove(true).run().unwrap();
+        assert_eq!(report.count(Status::Failed), 1);
+        assert!(!partial_path(&dir.path().join("a.tar")).exists());
+        assert!(dir.path().join("a/file").is_file());
+    }
+
+    #[test]
+    fn removing_a_missing_folder_is_not_an_error() {
+        let dir = tempfile::tempdir().unwrap();
+        let messages = crate::Messages::default();
+        let mut out = Output::new(&messages, false);
+        let removed = remove_dir(&dir.path().join("gone"), &Options::default(), &mut out);
+        assert!(!removed.unwrap());
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn retries_removal_with_backoff() {
+        let dir = target(&["a"]);
+        let file = dir.path().join("a/file");
+        // an immutable file cannot be removed even by root - Skip where attributes cannot be changed
+        let chattr = |flag| {
+            std::process::Command::new("chattr")
+                .arg(flag)
+                .arg(&file)
+                .status()
+        };
+        if !chattr("+i").is_ok_and(|status| status.success()) {
+            return;
+        }
+        let lines = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+        let sink = lines.clone();
+        let options = Options {
+            retries: 2,
+            backoff_ms: 1,
+            messages: crate::Messages::new(move |_, line: &str| {
+                sink.lock().unwrap().push(line.to_string())
+            }),
+            ..Options::default()
+        };
+        let mut out = Output::new(&options.messages, false);
+        let removed = remove_dir(&dir.path().join("a"), &options, &mut out);
+        chattr("-i").unwrap();
+        assert_eq!(
+            removed.unwrap_err().kind(),
+            std::io::ErrorKind::PermissionDenied
+        );
+        let lines = lines.lock().unwrap();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].ends_with("retrying in 1 ms (1/2)"), "{}", lines[0]);
+        assert!(lines[1].ends_with("retrying in 2 ms (2/2)"), "{}", lines[1]);
+        // the folder is left in place
+        assert!(file.exists());
+    }
+}
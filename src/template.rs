@@ -0,0 +1,355 @@
+use crate::checksum::{Algorithm, HashingWriter};
+use crate::error::Error;
+use crate::walk::{walk, with_path, Entry, Excludes, Kind};
+use chrono::format::{Item, StrftimeItems};
+use chrono::{DateTime, Local};
+use std::fmt;
+use std::io::Write;
+use std::path::Path;
+use std::str::FromStr;
+
+/// Format used by `{date}` and `{mtime}` when none is given
+const DEFAULT_DATE_FORMAT: &str = "%Y-%m-%d";
+
+/// A piece of a name template
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Part {
+    Literal(String),
+    /// The folder's name
+    Name,
+    /// When the run started, in the given strftime format
+    Date(String),
+    /// Host name of the machine
+    Host,
+    /// Latest modification time of anything in the folder, in the given strftime format
+    Mtime(String),
+    /// Total size in bytes of the files in the folder
+    Size,
+    /// First 8 hex digits of a SHA-256 hash of the folder's names and contents
+    Hash8,
+}
+
+/// Template for archive names, without the extension (e.g. `{name}-{date:%Y-%m-%d}`)
+///
+/// Placeholders are `{name}`, `{date}` or `{date:FORMAT}`, `{host}`, `{mtime}` or `{mtime:FORMAT}`, `{size}`
+/// and `{hash8}`, with `{{` and `}}` for literal braces. Formats are strftime style and default to `%Y-%m-%d`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct NameTemplate {
+    template: String,
+    parts: Vec<Part>,
+}
+
+impl Default for NameTemplate {
+    fn default() -> Self {
+        NameTemplate {
+            template: "{name}".to_string(),
+            parts: vec![Part::Name],
+        }
+    }
+}
+
+impl NameTemplate {
+    /// Renders the archive name (without extension) for a folder, with `{date}` taken from `now`
+    ///
+    /// The folder is only walked if the template uses `{mtime}`, `{size}` or `{hash8}`.
+    pub(crate) fn render(
+        &self,
+        folder_path: &Path,
+        excludes: &Excludes,
+        now: &DateTime<Local>,
+    ) -> Result<String, Error> {
+        let scan_error = |source| Error::Scan {
+            path: folder_path.to_path_buf(),
+            source,
+        };
+        let needs_entries = self
+            .parts
+            .iter()
+            .any(|part| matches!(part, Part::Mtime(_) | Part::Size | Part::Hash8));
+        let entries = match needs_entries {
+            true => walk(folder_path, excludes).map_err(scan_error)?,
+            false => Vec::new(),
+        };
+
+        let mut name = String::new();
+        for part in &self.parts {
+            match part {
+                Part::Literal(literal) => name.push_str(literal),
+                Part::Name => name.push_str(
+                    &folder_path
+                        .file_name()
+                        .unwrap_or_default()
+                        .to_string_lossy(),
+                ),
+                Part::Date(format) => name.push_str(&now.format(format).to_string()),
+                Part::Host => name.push_str(&gethostname::gethostname().to_string_lossy()),
+                Part::Mtime(format) => {
+                    let metadata = std::fs::metadata(folder_path).map_err(scan_error)?;
+                    let mtime = entries
+                        .iter()
+                        .map(|entry| &entry.metadata)
+                        .chain([&metadata])
+                        .filter_map(|metadata| metadata.modified().ok())
+                        .max()
+                        .map_or(*now, DateTime::<Local>::from);
+                    name.push_str(&mtime.format(format).to_string());
+                }
+                Part::Size => name.push_str(&size(&entries).to_string()),
+                Part::Hash8 => name.push_str(&hash(&entries).map_err(scan_error)?[..8]),
+            }
+        }
+
+        if name.is_empty() || name == "." || name == ".." || name.contains(['/', '\\']) {
+            return Err(Error::Options(format!(
+                "name template {:?} gives {:?} for {:?}, which is not a valid file name",
+                self.template, name, folder_path
+            )));
+        }
+        Ok(name)
+    }
+}
+
+/// Total size in bytes of the files in a folder
+fn size(entries: &[Entry]) -> u64 {
+    entries
+        .iter()
+        .filter(|entry| entry.kind() == Kind::File)
+        .map(|entry| entry.metadata.len())
+        .sum()
+}
+
+/// SHA-256 of the relative path of every entry in a folder followed by its contents, as lowercase hex
+///
+/// Only regular files are read, special files contribute just their name.
+fn hash(entries: &[Entry]) -> std::io::Result<String> {
+    let mut writer = HashingWriter::new(std::io::sink(), Some(Algorithm::Sha256.hasher()));
+    for entry in entries {
+        writer.write_all(entry.relative.to_string_lossy().as_bytes())?;
+        match entry.kind() {
+            Kind::Dir => writer.write_all(b"\0d\0")?,
+            Kind::Special | Kind::Unsupported => writer.write_all(b"\0s\0")?,
+            Kind::File => {
+                writer.write_all(b"\0f\0")?;
+                std::io::copy(
+                    &mut std::fs::File::open(&entry.path).map_err(with_path(&entry.path))?,
+                    &mut writer,
+                )?;
+            }
+        }
+    }
+    let (_, digest) = writer.finish();
+    Ok(digest.unwrap_or_default())
+}
+
+/// Checks that a strftime format only uses specifiers chrono understands
+fn date_format(format: Option<&str>) -> Result<String, String> {
+    let format = format.unwrap_or(DEFAULT_DATE_FORMAT);
+    match StrftimeItems::new(format).any(|item| item == Item::Error) {
+        true => Err(format!(
+            "invalid date format in name template: {:?}",
+            format
+        )),
+        false => Ok(format.to_string()),
+    }
+}
+
+impl FromStr for NameTemplate {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = Vec::new();
+        let mut literal = String::new();
+        let mut chars = s.chars();
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.as_str().starts_with('{') => {
+                    chars.next();
+                    literal.push('{');
+                }
+                '}' if chars.as_str().starts_with('}') => {
+                    chars.next();
+                    literal.push('}');
+                }
+                '{' => {
+                    let rest = chars.as_str();
+                    let Some(end) = rest.find('}') else {
+                        return Err(format!("unclosed '{{' in name template {:?}", s));
+                    };
+                    let placeholder = &rest[..end];
+                    chars = rest[end + 1..].chars();
+                    let (key, format) = match placeholder.split_once(':') {
+                        Some((key, format)) => (key, Some(format)),
+                        None => (placeholder, None),
+                    };
+                    let part = match (key, format) {
+                        ("name", None) => Part::Name,
+                        ("date", format) => Part::Date(date_format(format)?),
+                        ("host", None) => Part::Host,
+                        ("mtime", format) => Part::Mtime(date_format(format)?),
+                        ("size", None) => Part::Size,
+                        ("hash8", None) => Part::Hash8,
+                        _ => {
+                            return Err(format!(
+                                "unknown placeholder {{{}}} in name template (expected {{name}}, {{date}}, {{host}}, {{mtime}}, {{size}} or {{hash8}})",
+                                placeholder
+                            ))
+                        }
+                    };
+                    if !literal.is_empty() {
+                        parts.push(Part::Literal(std::mem::take(&mut literal)));
+                    }
+                    parts.push(part);
+                }
+                '}' => return Err(format!("unmatched '}}' in name template {:?}", s)),
+                '/' | '\\' => {
+                    return Err(format!(
+                        "name template {:?} must not contain path separators",
+                        s
+                    ))
+                }
+                c => literal.push(c),
+            }
+        }
+        if !literal.is_empty() {
+            parts.push(Part::Literal(literal));
+        }
+        if parts.is_empty() {
+            return Err("name template must not be empty".to_string());
+        }
+        Ok(NameTemplate {
+            template: s.to_string(),
+            parts,
+        })
+    }
+}
+
+impl fmt::Display for NameTemplate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.template)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn excludes() -> Excludes {
+        Excludes::new(&[], false, false, false).unwrap()
+    }
+
+    fn make_folder(files: &[(&str, &str)]) -> (tempfile::TempDir, std::path::PathBuf) {
+        let dir = tempfile::tempdir().unwrap();
+        let folder = dir.path().join("alpha");
+        std::fs::create_dir(&folder).unwrap();
+        for (name, contents) in files {
+            std::fs::write(folder.join(name), contents).unwrap();
+        }
+        (dir, folder)
+    }
+
+    #[test]
+    fn parses_placeholders_and_literals() {
+        let template = "{name}-{date}.{host}".parse::<NameTemplate>().unwrap();
+        assert_eq!(
+            template.parts,
+            vec![
+                Part::Name,
+                Part::Literal("-".to_string()),
+                Part::Date(DEFAULT_DATE_FORMAT.to_string()),
+                Part::Literal(".".to_string()),
+                Part::Host,
+            ]
+        );
+        assert_eq!(template.to_string(), "{name}-{date}.{host}");
+    }
+
+    #[test]
+    fn doubled_braces_are_literal() {
+        let template = "{{x}}{name}}}".parse::<NameTemplate>().unwrap();
+        assert_eq!(
+            template.parts,
+            vec![
+                Part::Literal("{x}".to_string()),
+                Part::Name,
+                Part::Literal("}".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn validates_date_formats() {
+        let template = "{date:%Y%m%d}-{mtime:%H}".parse::<NameTemplate>().unwrap();
+        assert_eq!(
+            template.parts,
+            vec![
+                Part::Date("%Y%m%d".to_string()),
+                Part::Literal("-".to_string()),
+                Part::Mtime("%H".to_string()),
+            ]
+        );
+        assert!("{date:%Q}".parse::<NameTemplate>().is_err());
+        assert!("{mtime:%}".parse::<NameTemplate>().is_err());
+    }
+
+    #[test]
+    fn rejects_invalid_templates() {
+        for template in ["", "a/b", "a\\b", "{name", "name}", "{nope}", "{name:%Y}"] {
+            assert!(
+                template.parse::<NameTemplate>().is_err(),
+                "{:?} should be rejected",
+                template
+            );
+        }
+    }
+
+    #[test]
+    fn renders_name_and_date() {
+        let (_dir, folder) = make_folder(&[]);
+        let now = Local.with_ymd_and_hms(2026, 10, 15, 12, 0, 0).unwrap();
+        let template = "{name}-{date}".parse::<NameTemplate>().unwrap();
+        assert_eq!(
+            template.render(&folder, &excludes(), &now).unwrap(),
+            "alpha-2026-10-15"
+        );
+    }
+
+    #[test]
+    fn renders_size_and_hash() {
+        let (_dir, folder) = make_folder(&[("a", "hello"), ("b", "hi")]);
+        let (_other_dir, other) = make_folder(&[("a", "hello"), ("b", "hi")]);
+        let now = Local::now();
+        let template = "{size}-{hash8}".parse::<NameTemplate>().unwrap();
+        let name = template.render(&folder, &excludes(), &now).unwrap();
+        let (size, hash) = name.split_once('-').unwrap();
+        assert_eq!(size, "7");
+        assert_eq!(hash.len(), 8);
+        // identical folders give identical hashes wherever they are
+        assert_eq!(template.render(&other, &excludes(), &now).unwrap(), name);
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn does_not_read_fifos() {
+        let (_dir, folder) = make_folder(&[("a", "hello")]);
+        let status = std::process::Command::new("mkfifo")
+            .arg(folder.join("pipe"))
+            .status()
+            .unwrap();
+        assert!(status.success());
+        let template = "{size}-{hash8}".parse::<NameTemplate>().unwrap();
+        let name = template
+            .render(&folder, &excludes(), &Local::now())
+            .unwrap();
+        assert!(name.starts_with("5-"), "{}", name);
+    }
+
+    #[test]
+    fn rejects_names_that_are_not_file_names() {
+        let (_dir, folder) = make_folder(&[]);
+        let template = "..".parse::<NameTemplate>().unwrap();
+        assert!(template
+            .render(&folder, &excludes(), &Local::now())
+            .is_err());
+    }
+}
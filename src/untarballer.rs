@@ -0,0 +1,746 @@
+use crate::compression::Compression;
+use crate::error::Error;
+use crate::events::Event;
+use crate::format::Format;
+use crate::output::say;
+use crate::report::{FolderReport, Report, Status};
+use crate::root::ArchiveRoot;
+use crate::walk::Totals;
+use crate::Options;
+use std::collections::HashMap;
+use std::fs::File;
+use std::path::{Component, Path, PathBuf};
+use std::time::Instant;
+
+/// An archive found in the target directory
+#[derive(Debug)]
+pub struct FoundArchive {
+    /// Location of the archive on disk
+    pub path: PathBuf,
+    pub format: Format,
+    pub compression: Compression,
+}
+
+/// Finds all archives in the target directory and returns a hashmap of folder names and archives
+///
+/// Archives that would extract into the same folder (`foo.tar` and `foo.tar.gz`) are listed together under it.
+pub(crate) fn archive_finder(
+    options: &Options,
+    current_dir: &Path,
+) -> Result<HashMap<String, Vec<FoundArchive>>, Error> {
+    let verbose = options.verbose;
+    let scan_error = |source| Error::Scan {
+        path: current_dir.to_path_buf(),
+        source,
+    };
+    // find current directory
+    if verbose {
+        say!(options.messages, "Working directory: {:?}", current_dir);
+    }
+
+    // start new hashmap for folder names
+    let mut folder_names_and_archives = HashMap::new();
+
+    // filter paths to only include files with an archive extension
+    let paths = std::fs::read_dir(current_dir).map_err(scan_error)?;
+    for path in paths {
+        let path = path.map_err(scan_error)?.path();
+        if verbose {
+            say!(options.messages, "Path: {:?}", path);
+        }
+        if !path.is_file() {
+            continue;
+        }
+        // archives with names that are not valid UTF-8 cannot be matched against an extension
+        let Some(file_name) = path.file_name().and_then(|name| name.to_str()) else {
+            continue;
+        };
+        let found = match Compression::from_file_name(file_name) {
+            Some((compression, folder_name)) => Some((folder_name, Format::Tar, compression)),
+            None => file_name
+                .strip_suffix(".zip")
+                .filter(|folder_name| !folder_name.is_empty())
+                .map(|folder_name| (folder_name, Format::Zip, Compression::None)),
+        };
+        match found {
+            Some((folder_name, format, compression)) => {
+                if verbose {
+                    say!(options.messages, "Archive path detected: {:?}", path);
+                    say!(options.messages, "Folder name: {:?}", folder_name);
+                }
+                folder_names_and_archives
+                    .entry(folder_name.to_string())
+                    .or_insert_with(Vec::new)
+                    .push(FoundArchive {
+                        path: path.clone(),
+                        format,
+                        compression,
+                    });
+            }
+            None => {
+                if verbose {
+                    say!(options.messages, "Not a recognised archive: {:?}", path);
+                }
+            }
+        }
+    }
+
+    // keep archives for the same folder in a predictable order
+    for archives in folder_names_and_archives.values_mut() {
+        archives.sort_by(|a: &FoundArchive, b: &FoundArchive| a.path.cmp(&b.path));
+    }
+
+    // print hashmap if verbose
+    if verbose {
+        say!(
+            options.messages,
+            "Folder names and archives: {:?}",
+            folder_names_and_archives
+        );
+    }
+
+    Ok(folder_names_and_archives)
+}
+
+/// Extracts the archives in the hashmap into folders named after each archive
+///
+/// Entries stored under `archive_root` have it stripped so the folder is not nested inside itself. Unless
+/// `keep_going` is set, stops at the first archive that fails - Archives that were never started are left out
+/// of the report. Archives that share a folder with another archive all fail, since only one could be extracted.
+pub(crate) fn untarballer(
+    options: &Options,
+    names_and_archives: HashMap<String, Vec<FoundArchive>>,
+    current_dir: &Path,
+    on_event: &dyn Fn(&Event),
+) -> Report {
+    on_event(&Event::Start {
+        folders: names_and_archives.values().map(Vec::len).sum(),
+    });
+
+    // start vec of reports, one per archive
+    let mut folders = Vec::new();
+
+    // iterate over hashmap and extract archives
+    for (folder_name, archives) in names_and_archives {
+        let folder_path = current_dir.join(&folder_name);
+        let reports = match <[FoundArchive; 1]>::try_from(archives) {
+            Ok([archive]) => {
+                on_event(&Event::FolderStart {
+                    folder: &folder_path,
+                    name: &file_name(&archive.path),
+                });
+                let started = Instant::now();
+                let mut report = untarball_archive(options, &archive, &folder_path);
+                report.duration = started.elapsed();
+                vec![report]
+            }
+            Err(archives) => {
+                let paths = archives
+                    .into_iter()
+                    .map(|archive| archive.path)
+                    .collect::<Vec<_>>();
+                let ambiguous = || Error::Ambiguous {
+                    folder: folder_path.clone(),
+                    archives: paths.clone(),
+                };
+                options.messages.error(format!("Error: {}", ambiguous()));
+                paths
+                    .iter()
+                    .map(|path| {
+                        let name = file_name(path);
+                        on_event(&Event::FolderStart {
+                            folder: &folder_path,
+                            name: &name,
+                        });
+                        let mut report = FolderReport::new(folder_path.clone(), name, path.clone());
+                        report.fail(ambiguous());
+                        report
+                    })
+                    .collect()
+            }
+        };
+        let failed = reports.iter().any(|report| report.status == Status::Failed);
+        for report in reports {
+            on_event(&Event::FolderDone(&report));
+            folders.push(report);
+        }
+        if failed && !options.keep_going {
+            break;
+        }
+    }
+    folders.sort_by(|a, b| a.folder.cmp(&b.folder));
+
+    let report = Report {
+        folders,
+        ..Report::default()
+    };
+    on_event(&Event::Report(&report));
+    report
+}
+
+/// Extracts a single archive into `folder_path`
+fn untarball_archive(
+    options: &Options,
+    archive: &FoundArchive,
+    folder_path: &Path,
+) -> FolderReport {
+    let (dry_run, verbose, remove) = (options.dry_run, options.verbose, options.remove);
+    if verbose {
+        say!(options.messages, "Archive path: {:?}", archive.path);
+        say!(options.messages, "Folder path: {:?}", folder_path);
+    }
+    let mut report = FolderReport::new(
+        folder_path.to_path_buf(),
+        file_name(&archive.path),
+        archive.path.clone(),
+    );
+    if folder_path.exists() {
+        say!(
+            options.messages,
+            "Folder already exists, skipping archive: {:?}",
+            archive.path
+        );
+        report.status = Status::Skipped;
+        return report;
+    }
+    match dry_run {
+        true => {
+            say!(
+                options.messages,
+                "Dry run - would extract archive: {:?}",
+                archive.path
+            );
+            match remove {
+                true => {
+                    say!(
+                        options.messages,
+                        "Dry run - would remove archive: {:?}",
+                        archive.path
+                    );
+                }
+                false => {
+                    say!(
+                        options.messages,
+                        "Dry run - would NOT remove archive: {:?}",
+                        archive.path
+                    );
+                }
+            }
+        }
+
+        false => {
+            if verbose {
+                say!(options.messages, "Extracting archive: {:?}", archive.path);
+            }
+            let totals = match extract(&options.archive_root, archive, folder_path) {
+                Ok(totals) => totals,
+                Err(source) => {
+                    // do not leave a half extracted folder behind, it would be skipped next time
+                    if source.kind() != std::io::ErrorKind::AlreadyExists {
+                        let _ = std::fs::remove_dir_all(folder_path);
+                    }
+                    let e = Error::Extract {
+                        archive: archive.path.clone(),
+                        folder: folder_path.to_path_buf(),
+                        source,
+                    };
+                    options.messages.error(format!("Error: {}", e));
+                    report.fail(e);
+                    return report;
+                }
+            };
+            report.bytes_in = std::fs::metadata(&archive.path).map_or(0, |m| m.len());
+            report.bytes_out = totals.bytes;
+            report.entries = totals.entries;
+            if verbose {
+                say!(options.messages, "Folder created: {:?}", folder_path);
+            }
+            report.status = Status::Archived;
+            match remove {
+                true => {
+                    if verbose {
+                        say!(options.messages, "Removing archive: {:?}", archive.path);
+                    }
+                    if let Err(source) = std::fs::remove_file(&archive.path) {
+                        let e = Error::Remove {
+                            path: archive.path.clone(),
+                            source,
+                        };
+                        options.messages.error(format!("Error: {}", e));
+                        report.fail(e);
+                        return report;
+                    }
+                    report.removed = true;
+                }
+                false => {
+                    if verbose {
+                        say!(options.messages, "Not removing archive: {:?}", archive.path);
+                    }
+                }
+            }
+        }
+    }
+    report
+}
+
+/// File name of an archive, as shown in the report
+fn file_name(path: &Path) -> String {
+    path.file_name()
+        .map_or_else(String::new, |name| name.to_string_lossy().into_owned())
+}
+
+/// Creates `folder_path` and extracts the archive into it, returning the number of entries and file bytes extracted
+fn extract(
+    archive_root: &ArchiveRoot,
+    archive: &FoundArchive,
+    folder_path: &Path,
+) -> std::io::Result<Totals> {
+    let root = match archive_root {
+        // the archive may not be named after the folder it was made from any more
+        ArchiveRoot::Folder => single_root(&entry_names(archive)?),
+        _ => archive_root.path(folder_path),
+    };
+    std::fs::create_dir(folder_path)?;
+    let file = File::open(&archive.path)?;
+    match archive.format {
+        Format::Tar => extract_tar(&root, file, archive.compression, folder_path),
+        Format::Zip => Ok(extract_zip(&root, file, folder_path)?),
+    }
+}
+
+/// Name of every entry in an archive, and whether it is a directory
+fn entry_names(archive: &FoundArchive) -> std::io::Result<Vec<(PathBuf, bool)>> {
+    let file = File::open(&archive.path)?;
+    let mut names = Vec::new();
+    match archive.format {
+        Format::Tar => {
+            let mut tarball = tar::Archive::new(archive.compression.decoder(file)?);
+            for entry in tarball.entries()? {
+                let entry = entry?;
+                names.push((
+                    entry.path()?.into_owned(),
+                    entry.header().entry_type().is_dir(),
+                ));
+            }
+        }
+        Format::Zip => {
+            let mut zip = zip::ZipArchive::new(file)?;
+            for index in 0..zip.len() {
+                let entry = zip.by_index(index)?;
+                if let Some(path) = entry.enclosed_name() {
+                    names.push((path, entry.is_dir()));
+                }
+            }
+        }
+    }
+    Ok(names)
+}
+
+/// The one directory every entry in an archive is under - Empty if there is more than one thing at the top level
+fn single_root(names: &[(PathBuf, bool)]) -> PathBuf {
+    let mut root = None;
+    for (path, is_dir) in names {
+        // entries escaping the folder are skipped when extracting, so they do not count
+        let Some(relative) = destination_path(Path::new(""), path) else {
+            continue;
+        };
+        let mut components = relative.components();
+        let Some(first) = components.next() else {
+            continue;
+        };
+        if components.next().is_none() && !is_dir {
+            return PathBuf::new();
+        }
+        match root {
+            None => root = Some(PathBuf::from(first.as_os_str())),
+            Some(ref root) if root.as_os_str() == first.as_os_str() => {}
+            Some(_) => return PathBuf::new(),
+        }
+    }
+    root.unwrap_or_default()
+}
+
+/// Extracts a (possibly compressed) tarball into `destination`
+fn extract_tar(
+    root: &Path,
+    file: File,
+    compression: Compression,
+    destination: &Path,
+) -> std::io::Result<Totals> {
+    let mut archive = tar::Archive::new(compression.decoder(file)?);
+    let mut totals = Totals::default();
+    let mut directories = Vec::new();
+    archive.set_preserve_permissions(true);
+    archive.set_preserve_mtime(true);
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let path = entry.path()?.into_owned();
+        let Some(relative) = destination_path(root, &path) else {
+            continue;
+        };
+        check_no_symlinks(destination, &relative)?;
+        let target = destination.join(&relative);
+        if let Some(parent) = target.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let entry_type = entry.header().entry_type();
+        if entry_type.is_dir() {
+            std::fs::create_dir_all(&target)?;
+            directories.push(Directory {
+                path: target,
+                mode: Some(entry.header().mode()?),
+                mtime: Some(entry.header().mtime()?),
+            });
+            totals.add(0);
+            continue;
+        }
+        if entry_type.is_hard_link() {
+            // hard links are resolved like any other entry so they can only point at files already extracted
+            let link_name = entry.link_name()?.unwrap_or_default();
+            let Some(link_relative) = destination_path(root, &link_name) else {
+                return Err(std::io::Error::other(format!(
+                    "{:?} links to {:?}, outside the archive",
+                    path, link_name
+                )));
+            };
+            check_no_symlinks(destination, &link_relative)?;
+            std::fs::hard_link(destination.join(link_relative), &target)?;
+            totals.add(0);
+            continue;
+        }
+        let size = match entry_type.is_file() {
+            true => entry.size(),
+            false => 0,
+        };
+        entry.unpack(target)?;
+        totals.add(size);
+    }
+    finish_directories(directories)?;
+    Ok(totals)
+}
+
+/// Extracts a zip archive into `destination`
+fn extract_zip(root: &Path, file: File, destination: &Path) -> zip::result::ZipResult<Totals> {
+    let mut archive = zip::ZipArchive::new(file)?;
+    let mut totals = Totals::default();
+    let mut directories = Vec::new();
+    for index in 0..archive.len() {
+        let mut entry = archive.by_index(index)?;
+        let Some(path) = entry.enclosed_name() else {
+            continue;
+        };
+        let Some(relative) = destination_path(root, &path) else {
+            continue;
+        };
+        check_no_symlinks(destination, &relative)?;
+        let target = destination.join(relative);
+        if entry.is_dir() {
+            std::fs::create_dir_all(&target)?;
+            directories.push(Directory {
+                path: target,
+                mode: entry.unix_mode(),
+                mtime: None,
+            });
+            totals.add(0);
+            continue;
+        }
+        if let Some(parent) = target.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        totals.add(std::io::copy(&mut entry, &mut File::create(&target)?)?);
+        if let Some(mode) = entry.unix_mode() {
+            set_mode(&target, mode)?;
+        }
+    }
+    finish_directories(directories)?;
+    Ok(totals)
+}
+
+/// A directory extracted from an archive, whose permissions and mtime are only applied once everything
+/// inside it has been extracted (a read-only directory could not be written to otherwise)
+struct Directory {
+    path: PathBuf,
+    mode: Option<u32>,
+    mtime: Option<u64>,
+}
+
+/// Applies the permissions and mtime of every extracted directory, deepest first
+fn finish_directories(mut directories: Vec<Directory>) -> std::io::Result<()> {
+    directories.sort_by(|a, b| b.path.cmp(&a.path));
+    for directory in directories {
+        if let Some(mtime) = directory.mtime {
+            let mtime = std::time::UNIX_EPOCH + std::time::Duration::from_secs(mtime);
+            File::open(&directory.path)?.set_modified(mtime)?;
+        }
+        if let Some(mode) = directory.mode {
+            set_mode(&directory.path, mode)?;
+        }
+    }
+    Ok(())
+}
+
+/// Sets the permissions of an extracted entry from the mode stored in the archive
+fn set_mode(path: &Path, mode: u32) -> std::io::Result<()> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode & 0o7777))?;
+    }
+    #[cfg(not(unix))]
+    let _ = (path, mode);
+    Ok(())
+}
+
+/// Fails if `relative`, or any directory on the way to it, is a symlink inside `destination`
+///
+/// Archives can hold symlinks, and extracting a later entry through one would write outside the new folder.
+fn check_no_symlinks(destination: &Path, relative: &Path) -> std::io::Result<()> {
+    let mut path = destination.to_path_buf();
+    for component in relative.components() {
+        path.push(component);
+        match std::fs::symlink_metadata(&path) {
+            Ok(metadata) if metadata.file_type().is_symlink() => {
+                return Err(std::io::Error::other(format!(
+                    "{:?} would be extracted through the symlink {:?}",
+                    relative, path
+                )));
+            }
+            Ok(_) => {}
+            // nothing below a missing path can be a symlink
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
+
+/// Where an archive entry should be extracted to, relative to the new folder
+///
+/// Entries stored under `root` (the directory around everything, as `wrap` creates them by default) have it
+/// stripped so the folder is not nested inside itself. Entries that would escape the folder are skipped.
+fn destination_path(root: &Path, path: &Path) -> Option<PathBuf> {
+    let mut relative = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::Normal(part) => relative.push(part),
+            Component::CurDir => {}
+            _ => return None,
+        }
+    }
+    let relative = match relative.strip_prefix(root) {
+        Ok(stripped) => stripped.to_path_buf(),
+        Err(_) => relative,
+    };
+    match relative.as_os_str().is_empty() {
+        true => None,
+        false => Some(relative),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn destination(root: &str, path: &str) -> Option<PathBuf> {
+        destination_path(Path::new(root), Path::new(path))
+    }
+
+    #[test]
+    fn strips_the_root() {
+        assert_eq!(destination("foo", "foo/a/b"), Some(PathBuf::from("a/b")));
+        assert_eq!(destination("foo", "./foo/a"), Some(PathBuf::from("a")));
+        assert_eq!(destination("a/b", "a/b/c"), Some(PathBuf::from("c")));
+        // the root entry itself is the new folder
+        assert_eq!(destination("foo", "foo"), None);
+        assert_eq!(destination("foo", "foo/"), None);
+    }
+
+    #[test]
+    fn keeps_entries_outside_the_root() {
+        assert_eq!(destination("", "a/b"), Some(PathBuf::from("a/b")));
+        assert_eq!(destination("foo", "bar/x"), Some(PathBuf::from("bar/x")));
+        // only whole components are stripped
+        assert_eq!(
+            destination("foo", "foobar/x"),
+            Some(PathBuf::from("foobar/x"))
+        );
+    }
+
+    #[test]
+    fn finds_the_single_root() {
+        let root = |names: &[(&str, bool)]| {
+            let names = names
+                .iter()
+                .map(|(name, is_dir)| (PathBuf::from(name), *is_dir))
+                .collect::<Vec<_>>();
+            single_root(&names)
+        };
+        // whatever the archive is called now
+        assert_eq!(
+            root(&[("b", true), ("b/a", false), ("b/c/d", false)]),
+            PathBuf::from("b")
+        );
+        assert_eq!(
+            root(&[("./b/a", false), ("b/c", false)]),
+            PathBuf::from("b")
+        );
+        assert_eq!(root(&[("b/a", false), ("c/a", false)]), PathBuf::new());
+        // a file at the top level means there is no root to strip
+        assert_eq!(root(&[("b", false)]), PathBuf::new());
+        assert_eq!(root(&[("b/a", false), ("c", false)]), PathBuf::new());
+        assert_eq!(root(&[]), PathBuf::new());
+    }
+
+    #[test]
+    fn rejects_entries_escaping_the_folder() {
+        assert_eq!(destination("foo", "../x"), None);
+        assert_eq!(destination("foo", "foo/../../x"), None);
+        assert_eq!(destination("foo", "/etc/passwd"), None);
+    }
+
+    /// Writes a tar archive of `(path, link)` entries - Entries without a link are files holding "data"
+    fn tar(dir: &Path, entries: &[(&str, Option<(tar::EntryType, &str)>)]) -> File {
+        let path = dir.join("archive.tar");
+        let mut builder = tar::Builder::new(File::create(&path).unwrap());
+        for (name, link) in entries {
+            let mut header = tar::Header::new_gnu();
+            header.set_mode(0o644);
+            match link {
+                Some((entry_type, target)) => {
+                    header.set_entry_type(*entry_type);
+                    header.set_size(0);
+                    builder.append_link(&mut header, name, target).unwrap();
+                }
+                None => {
+                    header.set_size(4);
+                    builder
+                        .append_data(&mut header, name, &b"data"[..])
+                        .unwrap();
+                }
+            }
+        }
+        builder.finish().unwrap();
+        File::open(path).unwrap()
+    }
+
+    #[test]
+    fn extracts_under_the_root() {
+        let dir = tempfile::tempdir().unwrap();
+        let archive = tar(dir.path(), &[("foo/a", None), ("foo/sub/b", None)]);
+        let folder = dir.path().join("foo");
+        std::fs::create_dir(&folder).unwrap();
+        let totals = extract_tar(Path::new("foo"), archive, Compression::None, &folder).unwrap();
+        assert_eq!((totals.entries, totals.bytes), (2, 8));
+        assert!(folder.join("a").is_file());
+        assert!(folder.join("sub/b").is_file());
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn refuses_to_extract_through_symlinks() {
+        let dir = tempfile::tempdir().unwrap();
+        let victim = dir.path().join("victim");
+        std::fs::create_dir(&victim).unwrap();
+        let archive = tar(
+            dir.path(),
+            &[
+                (
+                    "foo/link",
+                    Some((tar::EntryType::Symlink, victim.to_str().unwrap())),
+                ),
+                ("foo/link/owned.txt", None),
+            ],
+        );
+        let folder = dir.path().join("foo");
+        std::fs::create_dir(&folder).unwrap();
+        assert!(extract_tar(Path::new("foo"), archive, Compression::None, &folder).is_err());
+        assert!(!victim.join("owned.txt").exists());
+    }
+
+    #[test]
+    fn refuses_hard_links_outside_the_archive() {
+        let dir = tempfile::tempdir().unwrap();
+        let outside = dir.path().join("outside");
+        std::fs::write(&outside, "secret").unwrap();
+        let archive = tar(
+            dir.path(),
+            &[(
+                "foo/link",
+                Some((tar::EntryType::Link, outside.to_str().unwrap())),
+            )],
+        );
+        let folder = dir.path().join("foo");
+        std::fs::create_dir(&folder).unwrap();
+        assert!(extract_tar(Path::new("foo"), archive, Compression::None, &folder).is_err());
+        assert!(!folder.join("link").exists());
+    }
+
+    #[test]
+    fn resolves_hard_links_inside_the_archive() {
+        let dir = tempfile::tempdir().unwrap();
+        let archive = tar(
+            dir.path(),
+            &[
+                ("foo/a", None),
+                ("foo/b", Some((tar::EntryType::Link, "foo/a"))),
+            ],
+        );
+        let folder = dir.path().join("foo");
+        std::fs::create_dir(&folder).unwrap();
+        extract_tar(Path::new("foo"), archive, Compression::None, &folder).unwrap();
+        assert_eq!(std::fs::read(folder.join("b")).unwrap(), b"data");
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn applies_directory_modes_after_their_contents() {
+        use std::os::unix::fs::PermissionsExt;
+        let dir = tempfile::tempdir().unwrap();
+        let mode = |path: &Path| std::fs::metadata(path).unwrap().permissions().mode() & 0o7777;
+
+        let path = dir.path().join("archive.tar");
+        let mut builder = tar::Builder::new(File::create(&path).unwrap());
+        let mut header = tar::Header::new_gnu();
+        header.set_entry_type(tar::EntryType::Directory);
+        header.set_mode(0o555);
+        header.set_size(0);
+        builder
+            .append_data(&mut header, "foo/ro", std::io::empty())
+            .unwrap();
+        let mut header = tar::Header::new_gnu();
+        header.set_mode(0o644);
+        header.set_size(4);
+        builder
+            .append_data(&mut header, "foo/ro/a", &b"data"[..])
+            .unwrap();
+        builder.finish().unwrap();
+        let folder = dir.path().join("foo");
+        std::fs::create_dir(&folder).unwrap();
+        let file = File::open(path).unwrap();
+        extract_tar(Path::new("foo"), file, Compression::None, &folder).unwrap();
+        assert_eq!(std::fs::read(folder.join("ro/a")).unwrap(), b"data");
+        assert_eq!(mode(&folder.join("ro")), 0o555);
+
+        let path = dir.path().join("archive.zip");
+        let mut writer = zip::ZipWriter::new(File::create(&path).unwrap());
+        let options = zip::write::SimpleFileOptions::default();
+        writer
+            .add_directory("bar/ro", options.unix_permissions(0o555))
+            .unwrap();
+        writer
+            .start_file("bar/ro/a", options.unix_permissions(0o644))
+            .unwrap();
+        std::io::Write::write_all(&mut writer, b"data").unwrap();
+        writer.finish().unwrap();
+        let folder = dir.path().join("bar");
+        std::fs::create_dir(&folder).unwrap();
+        extract_zip(Path::new("bar"), File::open(path).unwrap(), &folder).unwrap();
+        assert_eq!(std::fs::read(folder.join("ro/a")).unwrap(), b"data");
+        assert_eq!(mode(&folder.join("ro")), 0o555);
+        // let the temporary directory be cleaned up
+        for folder in ["foo", "bar"] {
+            let path = dir.path().join(folder).join("ro");
+            std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o755)).unwrap();
+        }
+    }
+}
@@ -0,0 +1,353 @@
+use crate::compression::Compression;
+use crate::format::Format;
+use crate::walk::{archive_name, normalized_mode, walk, with_path, Excludes, Kind};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+use tar::EntryType;
+
+/// Permission bits compared between an archive and its folder (zip archives only keep these)
+const MODE_MASK: u32 = 0o777;
+
+/// Everything about a single entry that has to match between an archive and its folder
+///
+/// Only regular files have contents to hash, special files are compared by their kind alone.
+#[derive(Debug, PartialEq, Eq)]
+struct Summary {
+    kind: Kind,
+    size: u64,
+    mode: u32,
+    hash: Option<Vec<u8>>,
+}
+
+impl Summary {
+    /// Summary of an entry with nothing to compare but its kind
+    fn special(kind: Kind) -> Summary {
+        Summary {
+            kind,
+            size: 0,
+            mode: 0,
+            hash: None,
+        }
+    }
+}
+
+/// Re-reads a freshly written archive and compares every entry with the folder it was created from
+///
+/// Returns a description of every mismatch found, so an empty list means the archive is complete. Archives
+/// made with `reproducible` are compared against the normalised permissions of the folder.
+pub fn verify(
+    folder_path: &Path,
+    archive_path: &Path,
+    root: &Path,
+    format: Format,
+    compression: Compression,
+    excludes: &Excludes,
+    reproducible: bool,
+) -> std::io::Result<Vec<String>> {
+    let expected = folder_summaries(folder_path, root, excludes, reproducible)?;
+    let file = File::open(archive_path)?;
+    let actual = match format {
+        Format::Tar => tar_summaries(file, compression)?,
+        Format::Zip => zip_summaries(file).map_err(std::io::Error::other)?,
+    };
+
+    let mut mismatches = Vec::new();
+    for (name, expected) in &expected {
+        match actual.get(name) {
+            None => mismatches.push(format!("missing from archive: {}", name)),
+            Some(actual) if actual.kind != expected.kind => {
+                mismatches.push(format!("entry type differs: {}", name))
+            }
+            Some(actual) if actual.size != expected.size => mismatches.push(format!(
+                "size differs: {} (folder {} bytes, archive {} bytes)",
+                name, expected.size, actual.size
+            )),
+            Some(actual) if actual.mode != expected.mode => mismatches.push(format!(
+                "mode differs: {} (folder {:o}, archive {:o})",
+                name, expected.mode, actual.mode
+            )),
+            Some(actual) if actual.hash != expected.hash => {
+                mismatches.push(format!("content differs: {}", name))
+            }
+            Some(_) => {}
+        }
+    }
+    for name in actual.keys() {
+        if !expected.contains_key(name) {
+            mismatches.push(format!("not in folder: {}", name));
+        }
+    }
+    Ok(mismatches)
+}
+
+/// Summaries of the folder and everything below it, keyed by the name they should have in the archive
+fn folder_summaries(
+    folder_path: &Path,
+    root: &Path,
+    excludes: &Excludes,
+    reproducible: bool,
+) -> std::io::Result<BTreeMap<String, Summary>> {
+    let mode = |is_dir: bool, mode: u32| match reproducible {
+        true => normalized_mode(is_dir, mode),
+        false => mode & MODE_MASK,
+    };
+    let mut summaries = BTreeMap::new();
+    if !root.as_os_str().is_empty() {
+        let metadata = std::fs::metadata(folder_path)?;
+        summaries.insert(
+            archive_name(root),
+            Summary {
+                kind: Kind::Dir,
+                size: 0,
+                mode: mode(true, crate::walk::mode(&metadata)),
+                hash: None,
+            },
+        );
+    }
+    for entry in walk(folder_path, excludes)? {
+        let kind = entry.kind();
+        let summary = match kind {
+            Kind::File => Summary {
+                kind,
+                size: entry.metadata.len(),
+                mode: mode(false, entry.mode()),
+                hash: Some(hash(
+                    File::open(&entry.path).map_err(with_path(&entry.path))?,
+                )?),
+            },
+            Kind::Dir => Summary {
+                kind,
+                size: 0,
+                mode: mode(true, entry.mode()),
+                hash: None,
+            },
+            Kind::Special | Kind::Unsupported => Summary::special(kind),
+        };
+        summaries.insert(archive_name(&root.join(&entry.relative)), summary);
+    }
+    Ok(summaries)
+}
+
+/// Summaries of every entry in a (possibly compressed) tarball
+fn tar_summaries(
+    file: File,
+    compression: Compression,
+) -> std::io::Result<BTreeMap<String, Summary>> {
+    let mut archive = tar::Archive::new(compression.decoder(file)?);
+    let mut summaries = BTreeMap::new();
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let name = archive_name(&entry.path()?);
+        let kind = match entry.header().entry_type() {
+            EntryType::Directory => Kind::Dir,
+            EntryType::Regular | EntryType::Continuous => Kind::File,
+            EntryType::Fifo | EntryType::Char | EntryType::Block => Kind::Special,
+            _ => Kind::Unsupported,
+        };
+        let mode = entry.header().mode()? & MODE_MASK;
+        let summary = match kind {
+            Kind::File => Summary {
+                kind,
+                size: entry.header().size()?,
+                mode,
+                hash: Some(hash(&mut entry)?),
+            },
+            Kind::Dir => Summary {
+                kind,
+                size: 0,
+                mode,
+                hash: None,
+            },
+            Kind::Special | Kind::Unsupported => Summary::special(kind),
+        };
+        summaries.insert(name, summary);
+    }
+    Ok(summaries)
+}
+
+/// Summaries of every entry in a zip archive
+fn zip_summaries(file: File) -> zip::result::ZipResult<BTreeMap<String, Summary>> {
+    let mut archive = zip::ZipArchive::new(file)?;
+    let mut summaries = BTreeMap::new();
+    for index in 0..archive.len() {
+        let mut entry = archive.by_index(index)?;
+        let name = archive_name(Path::new(entry.name()?.as_ref()));
+        let is_dir = entry.is_dir();
+        let mode = entry.unix_mode().unwrap_or_default() & MODE_MASK;
+        let size = entry.size();
+        let hash = match is_dir {
+            true => None,
+            false => Some(hash(&mut entry)?),
+        };
+        summaries.insert(
+            name,
+            Summary {
+                kind: if is_dir { Kind::Dir } else { Kind::File },
+                size: if is_dir { 0 } else { size },
+                mode,
+                hash,
+            },
+        );
+    }
+    Ok(summaries)
+}
+
+/// SHA-256 of everything read from `reader`
+fn hash(mut reader: impl Read) -> std::io::Result<Vec<u8>> {
+    let mut hasher = Sha256::new();
+    let mut buffer = [0; 64 * 1024];
+    loop {
+        match reader.read(&mut buffer)? {
+            0 => break,
+            read => hasher.update(&buffer[..read]),
+        }
+    }
+    Ok(hasher.finalize().to_vec())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compression::Settings;
+    use crate::{tarball, zipper};
+    use std::path::PathBuf;
+
+    fn excludes() -> Excludes {
+        Excludes::new(&[], false, false, false).unwrap()
+    }
+
+    /// A folder named `foo` with a file and a subfolder in it
+    fn folder(dir: &Path) -> PathBuf {
+        let folder = dir.join("foo");
+        std::fs::create_dir_all(folder.join("sub")).unwrap();
+        std::fs::write(folder.join("a"), "hello").unwrap();
+        std::fs::write(folder.join("sub/b"), "hi").unwrap();
+        folder
+    }
+
+    fn archive(folder: &Path, root: &Path, format: Format, reproducible: Option<u64>) -> PathBuf {
+        let path = folder.with_extension(format.extension(Compression::None));
+        let file = File::create(&path).unwrap();
+        let settings = Settings::default();
+        match format {
+            Format::Tar => {
+                tarball::tar_folder(folder, root, file, &settings, &excludes(), reproducible)
+                    .unwrap();
+            }
+            Format::Zip => {
+                zipper::zip_folder(folder, root, file, &settings, &excludes(), reproducible)
+                    .unwrap();
+            }
+        }
+        path
+    }
+
+    fn check(folder: &Path, archive: &Path, root: &Path, format: Format) -> Vec<String> {
+        verify(
+            folder,
+            archive,
+            root,
+            format,
+            Compression::None,
+            &excludes(),
+            false,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn matching_archives_verify() {
+        let dir = tempfile::tempdir().unwrap();
+        let folder = folder(dir.path());
+        for format in [Format::Tar, Format::Zip] {
+            for root in [Path::new("foo"), Path::new("")] {
+                let archive = archive(&folder, root, format, None);
+                assert_eq!(check(&folder, &archive, root, format), Vec::<String>::new());
+            }
+        }
+    }
+
+    #[test]
+    fn reproducible_archives_verify() {
+        let dir = tempfile::tempdir().unwrap();
+        let folder = folder(dir.path());
+        for format in [Format::Tar, Format::Zip] {
+            let archive = archive(&folder, Path::new("foo"), format, Some(315532800));
+            let mismatches = verify(
+                &folder,
+                &archive,
+                Path::new("foo"),
+                format,
+                Compression::None,
+                &excludes(),
+                true,
+            )
+            .unwrap();
+            assert!(mismatches.is_empty(), "{:?}", mismatches);
+        }
+    }
+
+    #[test]
+    fn reports_changed_contents() {
+        let dir = tempfile::tempdir().unwrap();
+        let folder = folder(dir.path());
+        let archive = archive(&folder, Path::new("foo"), Format::Tar, None);
+        std::fs::write(folder.join("a"), "jello").unwrap();
+        assert_eq!(
+            check(&folder, &archive, Path::new("foo"), Format::Tar),
+            ["content differs: foo/a"]
+        );
+        std::fs::write(folder.join("a"), "hello, world").unwrap();
+        assert_eq!(
+            check(&folder, &archive, Path::new("foo"), Format::Tar),
+            ["size differs: foo/a (folder 12 bytes, archive 5 bytes)"]
+        );
+    }
+
+    #[test]
+    fn reports_missing_and_extra_entries() {
+        let dir = tempfile::tempdir().unwrap();
+        let folder = folder(dir.path());
+        let archive = archive(&folder, Path::new("foo"), Format::Zip, None);
+        std::fs::write(folder.join("new"), "").unwrap();
+        std::fs::remove_file(folder.join("sub/b")).unwrap();
+        assert_eq!(
+            check(&folder, &archive, Path::new("foo"), Format::Zip),
+            ["missing from archive: foo/new", "not in folder: foo/sub/b"]
+        );
+    }
+
+    #[test]
+    fn reports_a_different_root() {
+        let dir = tempfile::tempdir().unwrap();
+        let folder = folder(dir.path());
+        let archive = archive(&folder, Path::new("foo"), Format::Tar, None);
+        assert!(!check(&folder, &archive, Path::new("bar"), Format::Tar).is_empty());
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn compares_fifos_by_kind() {
+        let dir = tempfile::tempdir().unwrap();
+        let folder = folder(dir.path());
+        let status = std::process::Command::new("mkfifo")
+            .arg(folder.join("pipe"))
+            .status()
+            .unwrap();
+        assert!(status.success());
+        let tarball = archive(&folder, Path::new("foo"), Format::Tar, None);
+        assert_eq!(
+            check(&folder, &tarball, Path::new("foo"), Format::Tar),
+            Vec::<String>::new()
+        );
+        // zip cannot hold the fifo, so the archive is incomplete
+        let zip = archive(&folder, Path::new("foo"), Format::Zip, None);
+        assert_eq!(
+            check(&folder, &zip, Path::new("foo"), Format::Zip),
+            ["missing from archive: foo/pipe"]
+        );
+    }
+}
@@ -0,0 +1,459 @@
+use crate::output::Messages;
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use ignore::Match;
+use std::collections::HashSet;
+use std::fs::Metadata;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// Ignore file for wrap itself, using .gitignore syntax - Always honoured
+pub const WRAPIGNORE: &str = ".wrapignore";
+
+/// Version control metadata left out by `--exclude-vcs` (the same names GNU tar uses)
+pub const VCS_NAMES: &[&str] = &[
+    ".git",
+    ".gitignore",
+    ".gitattributes",
+    ".gitmodules",
+    ".hg",
+    ".hgignore",
+    ".hgtags",
+    ".hgsigs",
+    ".svn",
+    ".bzr",
+    ".bzrignore",
+    ".bzrtags",
+    "_darcs",
+    "CVS",
+    ".cvsignore",
+    "RCS",
+    "SCCS",
+    ".arch-ids",
+    "{arch}",
+];
+
+/// File marking a directory as a cache (see <https://bford.info/cachedir/>)
+pub const CACHEDIR_TAG: &str = "CACHEDIR.TAG";
+
+/// Header a cache directory tag file has to start with to be valid
+const CACHEDIR_SIGNATURE: &[u8] = b"Signature: 8a477f597d28d172789f06886806bc55";
+
+/// Whether a path is version control metadata
+pub fn is_vcs(path: &Path) -> bool {
+    path.file_name()
+        .and_then(|name| name.to_str())
+        .is_some_and(|name| VCS_NAMES.contains(&name))
+}
+
+/// Whether a directory contains a CACHEDIR.TAG file with a valid signature
+pub fn is_cache_dir(dir: &Path) -> bool {
+    let mut signature = [0; CACHEDIR_SIGNATURE.len()];
+    std::fs::File::open(dir.join(CACHEDIR_TAG))
+        .and_then(|mut tag| std::io::Read::read_exact(&mut tag, &mut signature))
+        .is_ok_and(|_| signature == CACHEDIR_SIGNATURE)
+}
+
+/// Rules deciding which entries below a folder are left out of its archive
+pub struct Excludes {
+    /// `--exclude-from-archive` patterns, using .gitignore syntax relative to the folder
+    patterns: Gitignore,
+    /// Whether .gitignore files are honoured as well as .wrapignore files
+    gitignore: bool,
+    /// Whether the contents of cache directories (except their CACHEDIR.TAG) are left out
+    caches: bool,
+    /// Whether version control metadata is left out
+    vcs: bool,
+    /// Ignore files already warned about, so folders walked more than once only warn once
+    warned: Mutex<HashSet<PathBuf>>,
+    /// Where warnings about ignore files go
+    messages: Messages,
+}
+
+impl Excludes {
+    /// Builds exclusion rules from .gitignore style patterns (e.g. `*.o` or `target/`)
+    pub fn new(
+        patterns: &[String],
+        gitignore: bool,
+        caches: bool,
+        vcs: bool,
+    ) -> Result<Self, String> {
+        let mut builder = GitignoreBuilder::new("");
+        for pattern in patterns {
+            builder
+                .add_line(None, pattern)
+                .map_err(|e| format!("invalid exclude pattern {:?}: {}", pattern, e))?;
+        }
+        let patterns = builder
+            .build()
+            .map_err(|e| format!("invalid exclude patterns: {}", e))?;
+        Ok(Excludes {
+            patterns,
+            gitignore,
+            caches,
+            vcs,
+            warned: Mutex::new(HashSet::new()),
+            messages: Messages::default(),
+        })
+    }
+
+    /// Sends warnings about ignore files that cannot be fully read to `messages`
+    pub fn with_messages(mut self, messages: Messages) -> Self {
+        self.messages = messages;
+        self
+    }
+
+    /// Reads the ignore files in a directory into a matcher rooted at that directory
+    ///
+    /// Patterns in .wrapignore are added last so they take precedence over .gitignore.
+    fn ignore_files(&self, dir: &Path) -> std::io::Result<Gitignore> {
+        let mut builder = GitignoreBuilder::new(dir);
+        let names = match self.gitignore {
+            true => &[".gitignore", WRAPIGNORE][..],
+            false => &[WRAPIGNORE][..],
+        };
+        for name in names {
+            let path = dir.join(name);
+            if !path.is_file() {
+                continue;
+            }
+            // like git, lines that cannot be parsed are skipped rather than failing the folder
+            if let Some(e) = builder.add(&path) {
+                if self.warned.lock().unwrap().insert(path.clone()) {
+                    // the error already names the file (and line)
+                    self.messages.error(format!("Warning: {}", e));
+                }
+            }
+        }
+        builder.build().map_err(std::io::Error::other)
+    }
+
+    /// Whether an entry is left out of the archive
+    ///
+    /// `--exclude-from-archive` patterns are checked first, then the ignore files from the innermost
+    /// directory outwards, so negated patterns (`!pattern`) can re-include entries ignored further up.
+    fn is_excluded(
+        &self,
+        path: &Path,
+        relative: &Path,
+        is_dir: bool,
+        ignore_files: &[Gitignore],
+    ) -> bool {
+        if self.vcs && is_vcs(path) {
+            return true;
+        }
+        let matched = std::iter::once(self.patterns.matched(relative, is_dir))
+            .chain(
+                ignore_files
+                    .iter()
+                    .rev()
+                    .map(|ignore_file| ignore_file.matched(path, is_dir)),
+            )
+            .find(|matched| !matched.is_none());
+        matches!(matched, Some(Match::Ignore(_)))
+    }
+}
+
+/// What sort of file an entry is, which decides how (and whether) it can be archived
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Kind {
+    Dir,
+    /// A regular file, the only kind with contents to read
+    File,
+    /// A FIFO or device node - Stored in tarballs as a header with no contents
+    Special,
+    /// A socket or anything else no archive format can hold
+    Unsupported,
+}
+
+impl Kind {
+    pub fn of(metadata: &Metadata) -> Kind {
+        let file_type = metadata.file_type();
+        if file_type.is_dir() {
+            return Kind::Dir;
+        }
+        if file_type.is_file() {
+            return Kind::File;
+        }
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::FileTypeExt;
+            if file_type.is_fifo() || file_type.is_char_device() || file_type.is_block_device() {
+                return Kind::Special;
+            }
+        }
+        Kind::Unsupported
+    }
+}
+
+/// A file or directory found while walking a folder
+pub struct Entry {
+    /// Location of the entry on disk
+    pub path: PathBuf,
+    /// Location of the entry relative to the folder being walked
+    pub relative: PathBuf,
+    /// Metadata of the entry (symlinks are followed)
+    pub metadata: Metadata,
+}
+
+impl Entry {
+    /// Permission bits to store for this entry in an archive
+    pub fn mode(&self) -> u32 {
+        mode(&self.metadata)
+    }
+
+    pub fn kind(&self) -> Kind {
+        Kind::of(&self.metadata)
+    }
+}
+
+/// Permission bits of a file or directory
+#[cfg(unix)]
+pub fn mode(metadata: &Metadata) -> u32 {
+    use std::os::unix::fs::PermissionsExt;
+    metadata.permissions().mode() & 0o7777
+}
+
+/// Permission bits of a file or directory
+#[cfg(not(unix))]
+pub fn mode(metadata: &Metadata) -> u32 {
+    match (metadata.is_dir(), metadata.permissions().readonly()) {
+        (true, _) => 0o755,
+        (false, true) => 0o444,
+        (false, false) => 0o644,
+    }
+}
+
+/// Number of entries in an archive and the total size of the files among them
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Totals {
+    pub entries: u64,
+    pub bytes: u64,
+    /// Entries left out because the archive format cannot hold them
+    pub skipped: Vec<PathBuf>,
+}
+
+impl Totals {
+    /// Counts one more entry, `size` bytes long (0 for directories)
+    pub fn add(&mut self, size: u64) {
+        self.entries += 1;
+        self.bytes += size;
+    }
+
+    /// Notes an entry that was left out of the archive
+    pub fn skip(&mut self, path: &Path) {
+        self.skipped.push(path.to_path_buf());
+    }
+}
+
+/// Permission bits stored in reproducible archives - 755 for directories and files their owner can execute,
+/// 644 for everything else
+pub fn normalized_mode(is_dir: bool, mode: u32) -> u32 {
+    match is_dir || mode & 0o100 != 0 {
+        true => 0o755,
+        false => 0o644,
+    }
+}
+
+/// Modification time in seconds since the Unix epoch, clamped so it is never later than `epoch`
+pub fn clamped_mtime(metadata: &Metadata, epoch: u64) -> u64 {
+    metadata
+        .modified()
+        .ok()
+        .and_then(|modified| modified.duration_since(std::time::UNIX_EPOCH).ok())
+        .map_or(epoch, |modified| modified.as_secs().min(epoch))
+}
+
+/// Recursively lists every file and directory below `root`, parents before children and siblings sorted by name
+///
+/// Excluded entries are skipped, and excluded directories are not walked into.
+pub fn walk(root: &Path, excludes: &Excludes) -> std::io::Result<Vec<Entry>> {
+    let mut entries = Vec::new();
+    walk_into(root, Path::new(""), excludes, &mut Vec::new(), &mut entries)?;
+    Ok(entries)
+}
+
+fn walk_into(
+    dir: &Path,
+    relative: &Path,
+    excludes: &Excludes,
+    ignore_files: &mut Vec<Gitignore>,
+    entries: &mut Vec<Entry>,
+) -> std::io::Result<()> {
+    // ignore files only apply to the directory they are in and below it
+    ignore_files.push(excludes.ignore_files(dir).map_err(with_path(dir))?);
+    let mut children = std::fs::read_dir(dir)
+        .and_then(|children| children.collect::<Result<Vec<_>, _>>())
+        .map_err(with_path(dir))?;
+    children.sort_by_key(|child| child.file_name());
+    for child in children {
+        let path = child.path();
+        let relative = relative.join(child.file_name());
+        let metadata = std::fs::metadata(&path).map_err(with_path(&path))?;
+        let is_dir = metadata.is_dir();
+        if excludes.is_excluded(&path, &relative, is_dir, ignore_files) {
+            continue;
+        }
+        entries.push(Entry {
+            path: path.clone(),
+            relative: relative.clone(),
+            metadata,
+        });
+        if is_dir && excludes.caches && is_cache_dir(&path) {
+            // like GNU tar, keep the tag so the directory is still recognisable as a cache
+            let tag_path = path.join(CACHEDIR_TAG);
+            entries.push(Entry {
+                metadata: std::fs::metadata(&tag_path).map_err(with_path(&tag_path))?,
+                path: tag_path,
+                relative: relative.join(CACHEDIR_TAG),
+            });
+        } else if is_dir {
+            walk_into(&path, &relative, excludes, ignore_files, entries)?;
+        }
+    }
+    ignore_files.pop();
+    Ok(())
+}
+
+/// Adds the path that could not be read to an error, which on its own rarely says which file it is about
+pub(crate) fn with_path(path: &Path) -> impl FnOnce(std::io::Error) -> std::io::Error + '_ {
+    move |e| std::io::Error::new(e.kind(), format!("{:?}: {}", path, e))
+}
+
+/// Converts an in-archive path into a `/` separated name
+pub fn archive_name(path: &Path) -> String {
+    path.components()
+        .map(|component| component.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Relative paths of everything walked below a folder
+    fn walked(folder: &Path, excludes: &Excludes) -> Vec<String> {
+        walk(folder, excludes)
+            .unwrap()
+            .into_iter()
+            .map(|entry| archive_name(&entry.relative))
+            .collect()
+    }
+
+    fn folder(files: &[(&str, &str)]) -> tempfile::TempDir {
+        let dir = tempfile::tempdir().unwrap();
+        for (path, contents) in files {
+            let path = dir.path().join(path);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, contents).unwrap();
+        }
+        dir
+    }
+
+    #[test]
+    fn honours_nested_ignore_files() {
+        let dir = folder(&[
+            (".gitignore", "*.log\n/build\n"),
+            ("keep.log.txt", ""),
+            ("a.log", ""),
+            ("build/out", ""),
+            ("sub/build/out", ""),
+            ("sub/.gitignore", "!important.log\n"),
+            ("sub/important.log", ""),
+            ("sub/other.log", ""),
+        ]);
+        let excludes = Excludes::new(&[], true, false, false).unwrap();
+        assert_eq!(
+            walked(dir.path(), &excludes),
+            [
+                ".gitignore",
+                "keep.log.txt",
+                "sub",
+                "sub/.gitignore",
+                "sub/build",
+                "sub/build/out",
+                "sub/important.log",
+            ]
+        );
+    }
+
+    #[test]
+    fn wrapignore_wins_over_gitignore() {
+        let dir = folder(&[
+            (".gitignore", "*.log\n"),
+            (WRAPIGNORE, "!a.log\nsecret\n"),
+            ("a.log", ""),
+            ("b.log", ""),
+            ("secret", ""),
+        ]);
+        let excludes = Excludes::new(&[], true, false, false).unwrap();
+        assert_eq!(
+            walked(dir.path(), &excludes),
+            [".gitignore", WRAPIGNORE, "a.log"]
+        );
+        // without .gitignore only .wrapignore applies
+        let excludes = Excludes::new(&[], false, false, false).unwrap();
+        assert_eq!(
+            walked(dir.path(), &excludes),
+            [".gitignore", WRAPIGNORE, "a.log", "b.log"]
+        );
+    }
+
+    #[test]
+    fn skips_lines_that_cannot_be_parsed() {
+        let dir = folder(&[(".gitignore", "[z-a]\n*.log\n"), ("a.log", ""), ("b", "")]);
+        let excludes = Excludes::new(&[], true, false, false).unwrap();
+        assert_eq!(walked(dir.path(), &excludes), [".gitignore", "b"]);
+    }
+
+    #[test]
+    fn leaves_out_entries_matching_exclude_patterns() {
+        let dir = folder(&[
+            ("a.o", ""),
+            ("keep.o", ""),
+            ("src/b.o", ""),
+            ("src/main.rs", ""),
+            ("target/out", ""),
+            ("src/target", ""),
+        ]);
+        let patterns = ["*.o", "!keep.o", "target/"].map(String::from);
+        let excludes = Excludes::new(&patterns, false, false, false).unwrap();
+        // `target/` only matches directories
+        assert_eq!(
+            walked(dir.path(), &excludes),
+            ["keep.o", "src", "src/main.rs", "src/target"]
+        );
+        assert!(Excludes::new(&["a{".to_string()], false, false, false).is_err());
+    }
+
+    #[test]
+    fn leaves_out_cache_contents_and_vcs_metadata() {
+        let tag = "Signature: 8a477f597d28d172789f06886806bc55\n# a cache";
+        let dir = folder(&[
+            ("cache/CACHEDIR.TAG", tag),
+            ("cache/blob", ""),
+            ("fake/CACHEDIR.TAG", "not a signature"),
+            ("fake/blob", ""),
+            (".git/HEAD", ""),
+            (".gitignore", ""),
+            ("src/.svn/entries", ""),
+            ("src/main.rs", ""),
+        ]);
+        let excludes = Excludes::new(&[], false, true, true).unwrap();
+        assert_eq!(
+            walked(dir.path(), &excludes),
+            [
+                "cache",
+                "cache/CACHEDIR.TAG",
+                "fake",
+                "fake/CACHEDIR.TAG",
+                "fake/blob",
+                "src",
+                "src/main.rs",
+            ]
+        );
+        assert!(is_cache_dir(&dir.path().join("cache")));
+        assert!(!is_cache_dir(&dir.path().join("fake")));
+        assert!(is_vcs(Path::new("/a/.hg")));
+    }
+}
@@ -0,0 +1,229 @@
+use crate::compression::{Compression, Settings};
+use crate::walk::{
+    archive_name, clamped_mtime, normalized_mode, walk, with_path, Excludes, Kind, Totals,
+};
+use std::fs::File;
+use std::io::Write;
+use std::path::Path;
+use zip::write::SimpleFileOptions;
+use zip::{CompressionMethod, DateTime, ZipWriter};
+
+/// Checks that the chosen compression can be expressed as a zip entry method
+pub fn validate(compression: &Settings) -> Result<(), String> {
+    match compression.codec {
+        Compression::None | Compression::Gzip => {}
+        codec => {
+            return Err(format!(
+                "zip archives only support stored (--compress none) or deflated (--compress gzip) entries (got --compress {})",
+                codec
+            ))
+        }
+    }
+    if compression.long.is_some() || compression.threads.is_some() {
+        return Err("--long and --threads are not supported for zip archives".to_string());
+    }
+    Ok(())
+}
+
+/// Creates a zip archive containing the folder and everything below it
+///
+/// The archive is streamed out sequentially (sizes and CRCs follow each entry) so the writer never has to seek.
+/// Entries are stored under `root`, or at the top level of the archive if it is empty. With `reproducible` set
+/// to a timestamp, modification times are clamped to it (in UTC, and no earlier than 1980, the first date zip
+/// can store) and permissions are normalised.
+///
+/// Zip has no way to store FIFOs, device nodes or sockets, so they are left out and listed in the totals.
+///
+/// Returns the writer along with the number of entries and file bytes that went into the archive.
+pub fn zip_folder<W: Write>(
+    folder_path: &Path,
+    root: &Path,
+    writer: W,
+    compression: &Settings,
+    excludes: &Excludes,
+    reproducible: Option<u64>,
+) -> zip::result::ZipResult<(W, Totals)> {
+    let options = match compression.codec {
+        Compression::Gzip => SimpleFileOptions::default()
+            .compression_method(CompressionMethod::Deflated)
+            .compression_level(compression.level.map(i64::from)),
+        _ => SimpleFileOptions::default().compression_method(CompressionMethod::Stored),
+    }
+    .large_file(true);
+
+    let mut archive = ZipWriter::new_stream(writer);
+    let mut totals = Totals::default();
+    if !root.as_os_str().is_empty() {
+        let metadata = std::fs::metadata(folder_path)?;
+        archive.add_directory(
+            archive_name(root),
+            entry_options(
+                options,
+                &metadata,
+                crate::walk::mode(&metadata),
+                reproducible,
+            ),
+        )?;
+        totals.add(0);
+    }
+    for entry in walk(folder_path, excludes)? {
+        let name = archive_name(&root.join(&entry.relative));
+        let options = entry_options(options, &entry.metadata, entry.mode(), reproducible);
+        match entry.kind() {
+            Kind::Dir => {
+                archive.add_directory(name, options)?;
+                totals.add(0);
+            }
+            Kind::File => {
+                archive.start_file(name, options)?;
+                totals.add(std::io::copy(
+                    &mut File::open(&entry.path).map_err(with_path(&entry.path))?,
+                    &mut archive,
+                )?);
+            }
+            Kind::Special | Kind::Unsupported => totals.skip(&entry.path),
+        }
+    }
+    Ok((archive.finish()?.into_inner(), totals))
+}
+
+/// Applies the permissions and modification time of a file on disk to its zip entry
+fn entry_options(
+    options: SimpleFileOptions,
+    metadata: &std::fs::Metadata,
+    mode: u32,
+    reproducible: Option<u64>,
+) -> SimpleFileOptions {
+    if let Some(epoch) = reproducible {
+        let mtime = chrono::DateTime::from_timestamp(clamped_mtime(metadata, epoch) as i64, 0)
+            .and_then(|mtime| DateTime::try_from(mtime.naive_utc()).ok())
+            .unwrap_or_default();
+        return options
+            .unix_permissions(normalized_mode(metadata.is_dir(), mode))
+            .last_modified_time(mtime);
+    }
+    let options = options.unix_permissions(mode);
+    match metadata
+        .modified()
+        .ok()
+        .map(|modified| chrono::DateTime::<chrono::Local>::from(modified).naive_local())
+        .and_then(|modified| DateTime::try_from(modified).ok())
+    {
+        Some(modified) => options.last_modified_time(modified),
+        None => options,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+
+    fn excludes() -> Excludes {
+        Excludes::new(&[], false, false, false).unwrap()
+    }
+
+    #[test]
+    fn rejects_codecs_zip_cannot_store() {
+        let settings = |codec| Settings {
+            codec,
+            ..Settings::default()
+        };
+        assert!(validate(&settings(Compression::Gzip)).is_ok());
+        assert!(validate(&settings(Compression::None)).is_ok());
+        assert!(validate(&settings(Compression::Zstd)).is_err());
+        assert!(validate(&settings(Compression::Xz)).is_err());
+    }
+
+    #[test]
+    fn round_trips_files_under_the_root() {
+        let dir = tempfile::tempdir().unwrap();
+        let folder = dir.path().join("foo");
+        std::fs::create_dir_all(folder.join("sub")).unwrap();
+        std::fs::write(folder.join("sub/a"), "hello").unwrap();
+        for codec in [Compression::None, Compression::Gzip] {
+            let settings = Settings {
+                codec,
+                ..Settings::default()
+            };
+            let (zip, totals) = zip_folder(
+                &folder,
+                Path::new("foo"),
+                std::io::Cursor::new(Vec::new()),
+                &settings,
+                &excludes(),
+                None,
+            )
+            .unwrap();
+            assert_eq!((totals.entries, totals.bytes), (3, 5));
+            let mut archive = zip::ZipArchive::new(zip).unwrap();
+            let mut names = archive
+                .file_names()
+                .map(|name| name.unwrap().to_string())
+                .collect::<Vec<_>>();
+            names.sort();
+            assert_eq!(names, ["foo/", "foo/sub/", "foo/sub/a"]);
+            let mut contents = String::new();
+            archive
+                .by_name("foo/sub/a")
+                .unwrap()
+                .read_to_string(&mut contents)
+                .unwrap();
+            assert_eq!(contents, "hello");
+        }
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn leaves_out_fifos() {
+        let dir = tempfile::tempdir().unwrap();
+        let folder = dir.path().join("foo");
+        std::fs::create_dir(&folder).unwrap();
+        std::fs::write(folder.join("a"), "hello").unwrap();
+        let status = std::process::Command::new("mkfifo")
+            .arg(folder.join("pipe"))
+            .status()
+            .unwrap();
+        assert!(status.success());
+        let (_, totals) = zip_folder(
+            &folder,
+            Path::new(""),
+            std::io::Cursor::new(Vec::new()),
+            &Settings::default(),
+            &excludes(),
+            None,
+        )
+        .unwrap();
+        assert_eq!(totals.entries, 1);
+        assert_eq!(totals.skipped, [folder.join("pipe")]);
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn reproducible_zips_are_identical() {
+        use std::os::unix::fs::PermissionsExt;
+        let zip = |dir: &Path, mode: u32| {
+            let folder = dir.join("foo");
+            std::fs::create_dir(&folder).unwrap();
+            std::fs::write(folder.join("a"), "hello").unwrap();
+            std::fs::set_permissions(folder.join("a"), std::fs::Permissions::from_mode(mode))
+                .unwrap();
+            zip_folder(
+                &folder,
+                Path::new("foo"),
+                std::io::Cursor::new(Vec::new()),
+                &Settings::default(),
+                &excludes(),
+                Some(315532800),
+            )
+            .unwrap()
+            .0
+            .into_inner()
+        };
+        let (first, second) = (tempfile::tempdir().unwrap(), tempfile::tempdir().unwrap());
+        // made at different times with different permissions
+        let first = zip(first.path(), 0o600);
+        std::thread::sleep(std::time::Duration::from_millis(2100));
+        assert_eq!(first, zip(second.path(), 0o640));
+    }
+}